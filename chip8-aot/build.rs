@@ -0,0 +1,39 @@
+//! Translates the bundled ROMs for `tests/translated.rs`, which checks that the translations
+//! compile and run like the interpreter.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/translate.rs"]
+#[allow(dead_code)]
+mod translate;
+
+fn main() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    println!("cargo:rerun-if-changed=src/translate.rs");
+    println!("cargo:rerun-if-changed={}", roms.display());
+
+    let mut paths: Vec<_> = fs::read_dir(&roms).expect("roms directory")
+        .map(|entry| entry.expect("roms directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ch8"))
+        .collect();
+    paths.sort();
+
+    let mut out = String::new();
+    let mut table = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let module = path.file_stem().unwrap().to_string_lossy().to_lowercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        let rom = fs::read(path).unwrap();
+        writeln!(out, "pub mod {} {{\n{}}}\n", module, translate::translate(&rom, &name)).unwrap();
+        table.push(format!("    ({:?}, {}::run),\n", name, module));
+    }
+    writeln!(out, "pub type Run = fn(&mut ProcState, u32) -> Result<(), Fault>;\n").unwrap();
+    writeln!(out, "/// Every bundled ROM with the `run` function translated from it.").unwrap();
+    writeln!(out, "pub const TRANSLATED: [(&str, Run); {}] = [\n{}];", table.len(), table.concat()).unwrap();
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("translated.rs");
+    fs::write(dest, out).unwrap();
+}
@@ -0,0 +1,44 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip8_core::source::RomSource;
+
+use crate::translate::translate;
+
+mod translate;
+
+const USAGE: &str = "usage: chip8-aot <rom> [-o <file>]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (filename, output) = match args.as_slice() {
+        [filename] => (filename, None),
+        [filename, flag, output] if flag == "-o" || flag == "--output" => (filename, Some(output)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let cart = match RomSource::parse(filename).load() {
+        Ok(cart) => cart,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            process::exit(1);
+        }
+    };
+    let name = Path::new(filename).file_name().map_or_else(|| filename.clone(), |name| name.to_string_lossy().to_string());
+    let code = translate(&cart.buffer[.. cart.size], &name);
+
+    match output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, code) {
+                eprintln!("{}: {}", output, e);
+                process::exit(1);
+            }
+        },
+        None => print!("{}", code)
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use chip8_core::cpu::metadata::Flow;
+use chip8_core::cpu::opcodes::{get_opcode, Opcode};
+use chip8_core::cpu::STARTING_PROGRAM_COUNTER;
+
+/// A straight run of reachable instructions, ending at the first one that may leave the run or
+/// writes memory, or just before another block starts.
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub ops: Vec<Opcode>
+}
+
+impl Block {
+    /// The address just past the block.
+    pub fn end(&self) -> u16 {
+        self.start + self.ops.len() as u16 * 2
+    }
+}
+
+/// Whether an instruction can be the last one run before control goes somewhere else, or
+/// changes the code that follows it. `DRW` and `LD Vx, K` may run again instead of moving on.
+fn ends_block(op: &Opcode) -> bool {
+    op.changes_flow()
+        || op.memory().is_some_and(|access| access.write)
+        || matches!(op, Opcode::DRW{..})
+}
+
+/// Where execution may go after the instruction at `addr`, as far as can be known statically.
+fn successors(addr: u16, op: &Opcode) -> Vec<u16> {
+    let next = addr.wrapping_add(2);
+    match op.flow() {
+        Flow::Next if matches!(op, Opcode::DRW{..}) => vec![addr, next],
+        Flow::Next => vec![next],
+        Flow::Skip => vec![next, next.wrapping_add(2)],
+        Flow::Jump(target) => vec![target],
+        Flow::Call(target) => vec![target, next],
+        Flow::Wait => vec![addr, next],
+        Flow::IndirectJump(_) | Flow::Return | Flow::Fault => vec![]
+    }
+}
+
+/// Finds the blocks reachable from the entry point of a ROM loaded at 0x200. Code outside the
+/// ROM and code only reached through `JP V0` is left to the interpreter.
+pub fn find_blocks(rom: &[u8]) -> Vec<Block> {
+    let start = STARTING_PROGRAM_COUNTER as usize;
+    let decode = |addr: u16| -> Option<Opcode> {
+        let offset = (addr as usize).checked_sub(start)?;
+        let bytes = rom.get(offset .. offset + 2)?;
+        Some(get_opcode((bytes[0] as u16) << 8 | bytes[1] as u16))
+    };
+
+    // Every instruction a block can start at: the entry point and wherever control goes after
+    // the last instruction of a block
+    let mut reachable = BTreeSet::new();
+    let mut leaders = BTreeSet::from([STARTING_PROGRAM_COUNTER]);
+    let mut pending = vec![STARTING_PROGRAM_COUNTER];
+    while let Some(addr) = pending.pop() {
+        let op = match decode(addr) {
+            Some(op) if reachable.insert(addr) => op,
+            _ => continue
+        };
+        let targets = successors(addr, &op);
+        if ends_block(&op) {
+            leaders.extend(&targets);
+        }
+        pending.extend(targets);
+    }
+
+    leaders.iter()
+        .filter(|addr| reachable.contains(addr))
+        .map(|&start| {
+            let mut ops = Vec::new();
+            let mut addr = start;
+            while let Some(op) = decode(addr) {
+                ops.push(op);
+                addr += 2;
+                if ends_block(&op) || leaders.contains(&addr) {
+                    break;
+                }
+            }
+            Block { start, ops }
+        })
+        .collect()
+}
+
+/// Rust for an instruction whose result does not depend on the quirks, or `None` if it has to
+/// go through `ProcState::try_execute_opcode`. `next` is the address of the instruction after.
+fn inline(op: &Opcode, next: u16) -> Option<String> {
+    let skip = |condition: String| {
+        format!("state.pc = if {} {{ {:#05x} }} else {{ {:#05x} }};", condition, next.wrapping_add(2), next)
+    };
+    let code = match *op {
+        Opcode::JP{addr} => format!("state.pc = {:#05x};", addr),
+        Opcode::SEVxByte{x, byte} => skip(format!("state.vreg[{:#x}] == {:#04x}", x, byte)),
+        Opcode::SNEVxByte{x, byte} => skip(format!("state.vreg[{:#x}] != {:#04x}", x, byte)),
+        Opcode::SEVxVy{x, y} => skip(format!("state.vreg[{:#x}] == state.vreg[{:#x}]", x, y)),
+        Opcode::SNEVxVy{x, y} => skip(format!("state.vreg[{:#x}] != state.vreg[{:#x}]", x, y)),
+        Opcode::LDVxByte{x, byte} => format!("state.vreg[{:#x}] = {:#04x};", x, byte),
+        Opcode::ADDVxByte{x, byte} => format!("state.vreg[{:#x}] = state.vreg[{:#x}].wrapping_add({:#04x});", x, x, byte),
+        Opcode::LDVxVy{x, y} if x == y => String::new(),
+        Opcode::LDVxVy{x, y} => format!("state.vreg[{:#x}] = state.vreg[{:#x}];", x, y),
+        Opcode::LDIAddr{addr} => format!("state.ireg = {:#05x};", addr),
+        Opcode::ADDIVx{x} => format!("state.ireg = state.ireg.wrapping_add(state.vreg[{:#x}] as u16);", x),
+        Opcode::LDVxDT{x} => format!("state.vreg[{:#x}] = state.delay_t;", x),
+        Opcode::LDDTVx{x} => format!("state.delay_t = state.vreg[{:#x}];", x),
+        Opcode::LDSTVx{x} => format!("state.sound_t = state.vreg[{:#x}];", x),
+        _ => return None
+    };
+    Some(code)
+}
+
+/// The function running a block.
+fn block_fn(block: &Block) -> String {
+    let mut body = String::new();
+    let mut addr = block.start;
+    for (i, op) in block.ops.iter().enumerate() {
+        let next = addr + 2;
+        let last = i + 1 == block.ops.len();
+        writeln!(body, "    // {:#05x}: {}", addr, op).unwrap();
+        match inline(op, next) {
+            Some(code) => {
+                if !code.is_empty() {
+                    writeln!(body, "    {}", code).unwrap();
+                }
+                if last && op.flow() == Flow::Next {
+                    writeln!(body, "    state.pc = {:#05x};", next).unwrap();
+                }
+            },
+            None => {
+                // The interpreter moves on from the program counter past the instruction
+                if last {
+                    writeln!(body, "    state.pc = {:#05x};", next).unwrap();
+                }
+                writeln!(body, "    state.try_execute_opcode(Opcode::{:?})?;", op).unwrap();
+            }
+        }
+        addr = next;
+    }
+
+    format!("fn block_{:03x}(state: &mut ProcState) -> Result<(), Fault> {{\n{}    Ok(())\n}}\n", block.start, body)
+}
+
+/// Translates a ROM into a Rust module with a `run` function that behaves like `Machine::run`
+/// without a step callback. It needs `chip8-core` and nothing else.
+pub fn translate(rom: &[u8], name: &str) -> String {
+    let blocks = find_blocks(rom);
+    let mut out = String::new();
+
+    writeln!(out, "// Translated from {} by chip8-aot. Regenerate it rather than editing it.", name).unwrap();
+    writeln!(out).unwrap();
+    if blocks.iter().flat_map(|block| &block.ops).any(|op| inline(op, 0).is_none()) {
+        writeln!(out, "use chip8_core::cpu::opcodes::Opcode;").unwrap();
+    }
+    writeln!(out, "use chip8_core::cpu::{{Fault, ProcState}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "/// Where the ROM is loaded.").unwrap();
+    writeln!(out, "pub const LOAD_ADDRESS: usize = {:#05x};", STARTING_PROGRAM_COUNTER).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "/// The ROM the code was translated from.").unwrap();
+    writeln!(out, "pub const ROM: [u8; {}] = [", rom.len()).unwrap();
+    for line in rom.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:#04x}", b)).collect();
+        writeln!(out, "    {},", bytes.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// Executes up to `budget` instructions, running the translated code for every block whose").unwrap();
+    writeln!(out, "/// memory still holds the ROM and interpreting everything else.").unwrap();
+    writeln!(out, "pub fn run(state: &mut ProcState, budget: u32) -> Result<(), Fault> {{").unwrap();
+    writeln!(out, "    let mut remaining = budget;").unwrap();
+    writeln!(out, "    while remaining > 0 {{").unwrap();
+    writeln!(out, "        remaining -= match state.pc {{").unwrap();
+    for block in &blocks {
+        let len = block.ops.len();
+        writeln!(out, "            {:#05x} if remaining >= {} && unchanged(state, {:#05x}, {:#05x}) => {{", block.start, len, block.start, block.end()).unwrap();
+        writeln!(out, "                block_{:03x}(state)?;", block.start).unwrap();
+        writeln!(out, "                {}", len).unwrap();
+        writeln!(out, "            }},").unwrap();
+    }
+    writeln!(out, "            _ => {{").unwrap();
+    writeln!(out, "                let opcode = state.fetch_and_decode_opcode();").unwrap();
+    writeln!(out, "                state.try_execute_opcode(opcode)?;").unwrap();
+    writeln!(out, "                1").unwrap();
+    writeln!(out, "            }}").unwrap();
+    writeln!(out, "        }};").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "    Ok(())").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// Whether memory from `start` to `end` still holds the ROM, so code translated from it is valid.").unwrap();
+    writeln!(out, "fn unchanged(state: &ProcState, start: usize, end: usize) -> bool {{").unwrap();
+    writeln!(out, "    state.mem[start .. end] == ROM[start - LOAD_ADDRESS .. end - LOAD_ADDRESS]").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    for block in &blocks {
+        writeln!(out).unwrap();
+        out.push_str(&block_fn(block));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn blocks_split_at_control_flow_and_jump_targets() {
+        let rom = [
+            0x60, 0x01, // 0x200: LD V0, 0x01
+            0x70, 0x01, // 0x202: ADD V0, 0x01
+            0x30, 0x10, // 0x204: SE V0, 0x10
+            0x12, 0x02, // 0x206: JP 0x202
+            0x12, 0x08, // 0x208: JP 0x208
+        ];
+
+        let starts: Vec<(u16, usize)> = find_blocks(&rom).iter().map(|b| (b.start, b.ops.len())).collect();
+
+        assert_eq!(starts, vec![(0x200, 1), (0x202, 2), (0x206, 1), (0x208, 1)]);
+    }
+
+    #[test]
+    pub fn unreachable_and_indirect_code_is_not_translated() {
+        let rom = [
+            0xB2, 0x04, // 0x200: JP V0, 0x204
+            0x60, 0x01, // 0x202: LD V0, 0x01
+            0x60, 0x02, // 0x204: LD V0, 0x02
+        ];
+
+        let blocks = find_blocks(&rom);
+
+        assert_eq!(blocks, vec![Block { start: 0x200, ops: vec![Opcode::JPV0Addr { addr: 0x204 }] }]);
+    }
+
+    #[test]
+    pub fn memory_writes_end_a_block() {
+        let rom = [
+            0xF1, 0x55, // 0x200: LD [I], V1
+            0x60, 0x01, // 0x202: LD V0, 0x01
+            0x12, 0x02, // 0x204: JP 0x202
+        ];
+
+        let starts: Vec<u16> = find_blocks(&rom).iter().map(|b| b.start).collect();
+
+        assert_eq!(starts, vec![0x200, 0x202]);
+    }
+
+    #[test]
+    pub fn translation_interprets_quirky_instructions() {
+        let rom = [
+            0x60, 0x01, // 0x200: LD V0, 0x01
+            0x80, 0x16, // 0x202: SHR V0, V1
+            0x12, 0x04, // 0x204: JP 0x204
+        ];
+
+        let code = translate(&rom, "test.ch8");
+
+        assert!(code.contains("state.vreg[0x0] = 0x01;"));
+        assert!(code.contains("state.try_execute_opcode(Opcode::SHRVxVy { x: 0, y: 1 })?;"));
+        assert!(code.contains("0x200 if remaining >= 2 && unchanged(state, 0x200, 0x204) =>"));
+    }
+}
@@ -0,0 +1,52 @@
+//! Runs every bundled ROM through the code `build.rs` translated from it and through the
+//! interpreter, which must end up in the same state.
+
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use chip8_core::cart::Cartridge;
+use chip8_core::cpu::{Fault, ProcState};
+use chip8_core::machine::Machine;
+
+include!(concat!(env!("OUT_DIR"), "/translated.rs"));
+
+const FRAMES: u32 = 120;
+const TICKRATE: u32 = 500;
+
+fn machine(name: &str) -> Machine {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms").join(name);
+    let cart = Cartridge::load(&mut fs::File::open(path).unwrap()).unwrap();
+    let keypad = Rc::new(Cell::new(Some(0x0)));
+    Machine::builder(&cart).seed(Some(0)).keypad(keypad).build().expect("a cartridge always fits at 0x200")
+}
+
+#[test]
+pub fn translated_roms_match_the_interpreter() {
+    let mut failures = Vec::new();
+    for (name, run) in TRANSLATED.iter() {
+        let mut interpreted = machine(name);
+        let mut translated = machine(name);
+
+        for frame in 0 .. FRAMES {
+            let expected = interpreted.run(TICKRATE, |_, _| ());
+            let actual = run(&mut translated.state, TICKRATE);
+            let (a, b) = (&interpreted.state, &translated.state);
+            let same = expected == actual && a.video_buffer == b.video_buffer && a.vreg == b.vreg
+                && a.ireg == b.ireg && a.pc == b.pc && a.sp == b.sp && a.stack == b.stack
+                && a.delay_t == b.delay_t && a.sound_t == b.sound_t && a.mem[..] == b.mem[..];
+            if !same {
+                failures.push(format!("{}: differs after frame {}\n  interpreter {} {:?}\n  translation {} {:?}", name, frame, a, expected, b, actual));
+                break;
+            }
+            if expected.is_err() {
+                break;
+            }
+            interpreted.state.clock_tick(1);
+            translated.state.clock_tick(1);
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,174 @@
+//! Writes the opcode test vectors in `tests/vectors/opcodes.json` from the current
+//! implementation:
+//!
+//!     cargo run -p chip8-core --example generate_vectors > chip8-core/tests/vectors/opcodes.json
+//!
+//! Each case runs under every quirk profile, and profiles that end in the same state share a
+//! vector. Cases can also state what must hold afterwards under every profile, so a broken
+//! instruction fails here instead of being written out as the expected result.
+
+use chip8_core::vectors::{profile_quirks, profiles, MemoryFragment, Snapshot, TestVector};
+
+/// Where execution continues after an instruction fetched from 0x200.
+const PC: u16 = 0x202;
+
+/// What must hold for the state after an instruction, whatever the profile, and a check for it.
+type Requirement = (&'static str, fn(&Snapshot) -> bool);
+
+struct Case {
+    name: &'static str,
+    opcode: u16,
+    initial: Snapshot,
+    requires: Option<Requirement>
+}
+
+impl Case {
+    fn requires(mut self, what: &'static str, check: fn(&Snapshot) -> bool) -> Case {
+        self.requires = Some((what, check));
+        self
+    }
+}
+
+fn case(name: &'static str, opcode: u16, setup: impl FnOnce(&mut Snapshot)) -> Case {
+    let mut initial = Snapshot { pc: PC, ..Snapshot::default() };
+    setup(&mut initial);
+    Case { name, opcode, initial, requires: None }
+}
+
+fn regs(snapshot: &mut Snapshot, values: &[(usize, u8)]) {
+    for (reg, value) in values {
+        snapshot.v[*reg] = *value;
+    }
+}
+
+fn memory(snapshot: &mut Snapshot, address: u16, bytes: &[u8]) {
+    snapshot.memory.push(MemoryFragment { address, bytes: bytes.to_vec() });
+}
+
+fn row(pixels: &[usize]) -> String {
+    (0 .. 64).map(|x| if pixels.contains(&x) { '#' } else { '.' }).collect()
+}
+
+/// An 8x2 sprite at 0x300 and I pointing at it, ready for `DRW`.
+fn sprite(snapshot: &mut Snapshot) {
+    snapshot.i = 0x300;
+    snapshot.vblank = true;
+    memory(snapshot, 0x300, &[0xFF, 0x81]);
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        case("CLS with pixels lit", 0x00E0, |s| { s.screen.insert(0, row(&[0, 63])); })
+            .requires("a blank screen", |s| s.screen.is_empty()),
+        case("RET", 0x00EE, |s| s.stack = vec![0x204, 0x240])
+            .requires("PC popped from the stack", |s| s.pc == 0x240 && s.stack == [0x204]),
+        case("JP", 0x1345, |_| ()).requires("PC at the address", |s| s.pc == 0x345),
+        case("CALL", 0x2345, |s| s.stack = vec![0x204])
+            .requires("the return address pushed", |s| s.pc == 0x345 && s.stack == [0x204, PC]),
+        case("CALL filling the stack", 0x2345, |s| s.stack = (0 .. 14).map(|n| 0x200 + n * 2).collect()),
+        case("SE Vx, byte equal", 0x3A42, |s| regs(s, &[(0xA, 0x42)])),
+        case("SE Vx, byte not equal", 0x3A42, |s| regs(s, &[(0xA, 0x41)])),
+        case("SNE Vx, byte equal", 0x4A42, |s| regs(s, &[(0xA, 0x42)])),
+        case("SNE Vx, byte not equal", 0x4A42, |s| regs(s, &[(0xA, 0x41)])),
+        case("SE Vx, Vy equal", 0x5120, |s| regs(s, &[(0x1, 0x7), (0x2, 0x7)])),
+        case("SE Vx, Vy not equal", 0x5120, |s| regs(s, &[(0x1, 0x7), (0x2, 0x8)])),
+        case("LD Vx, byte", 0x6C9A, |_| ()).requires("the byte in Vx", |s| s.v[0xC] == 0x9A),
+        case("LD VF, byte", 0x6F01, |_| ()),
+        case("ADD Vx, byte", 0x7310, |s| regs(s, &[(0x3, 0x20), (0xF, 0x55)])),
+        case("ADD Vx, byte overflowing leaves VF", 0x7310, |s| regs(s, &[(0x3, 0xF8), (0xF, 0x55)])),
+        case("LD Vx, Vy", 0x8450, |s| regs(s, &[(0x4, 0x01), (0x5, 0xAB)])),
+        case("OR Vx, Vy", 0x8451, |s| regs(s, &[(0x4, 0x0F), (0x5, 0xF0), (0xF, 0x01)])),
+        case("AND Vx, Vy", 0x8452, |s| regs(s, &[(0x4, 0x3C), (0x5, 0x0F), (0xF, 0x01)])),
+        case("XOR Vx, Vy", 0x8453, |s| regs(s, &[(0x4, 0xFF), (0x5, 0x0F), (0xF, 0x01)])),
+        case("ADD Vx, Vy", 0x8454, |s| regs(s, &[(0x4, 0x10), (0x5, 0x20), (0xF, 0x01)])),
+        case("ADD Vx, Vy with carry", 0x8454, |s| regs(s, &[(0x4, 0xF0), (0x5, 0x20)])),
+        case("ADD VF, Vy sets the flag last", 0x8F54, |s| regs(s, &[(0x5, 0x20), (0xF, 0xF0)])),
+        case("ADD Vx, VF", 0x84F4, |s| regs(s, &[(0x4, 0x01), (0xF, 0xFF)])),
+        case("SUB Vx, Vy", 0x8455, |s| regs(s, &[(0x4, 0x30), (0x5, 0x10)])),
+        case("SUB Vx, Vy with borrow", 0x8455, |s| regs(s, &[(0x4, 0x10), (0x5, 0x30), (0xF, 0x01)])),
+        case("SUB Vx, Vy equal", 0x8455, |s| regs(s, &[(0x4, 0x10), (0x5, 0x10)])),
+        case("SUB VF, Vy sets the flag last", 0x8F55, |s| regs(s, &[(0x5, 0x10), (0xF, 0x05)])),
+        case("SHR Vx, Vy", 0x8456, |s| regs(s, &[(0x4, 0x81), (0x5, 0x02)])),
+        case("SHR Vx, Vy shifting out a one", 0x8456, |s| regs(s, &[(0x4, 0x02), (0x5, 0x03)])),
+        case("SHR VF, Vy sets the flag last", 0x8F56, |s| regs(s, &[(0x5, 0x03), (0xF, 0x03)])),
+        case("SUBN Vx, Vy", 0x8457, |s| regs(s, &[(0x4, 0x10), (0x5, 0x30)])),
+        case("SUBN Vx, Vy with borrow", 0x8457, |s| regs(s, &[(0x4, 0x30), (0x5, 0x10), (0xF, 0x01)])),
+        case("SUBN VF, Vy sets the flag last", 0x8F57, |s| regs(s, &[(0x5, 0x30), (0xF, 0x10)])),
+        case("SHL Vx, Vy", 0x845E, |s| regs(s, &[(0x4, 0x01), (0x5, 0x40)])),
+        case("SHL Vx, Vy shifting out a one", 0x845E, |s| regs(s, &[(0x4, 0x81), (0x5, 0xC0)])),
+        case("SHL VF, Vy sets the flag last", 0x8F5E, |s| regs(s, &[(0x5, 0xC0), (0xF, 0xC0)])),
+        case("SNE Vx, Vy equal", 0x9120, |s| regs(s, &[(0x1, 0x7), (0x2, 0x7)])),
+        case("SNE Vx, Vy not equal", 0x9120, |s| regs(s, &[(0x1, 0x7), (0x2, 0x8)])),
+        case("LD I, addr", 0xA123, |_| ()),
+        case("JP V0, addr", 0xB340, |s| regs(s, &[(0x0, 0x10), (0x3, 0x20)])),
+        case("RND Vx, 0", 0xC700, |s| regs(s, &[(0x7, 0xFF)])),
+        case("DRW", 0xD122, |s| { sprite(s); regs(s, &[(0x1, 0x8), (0x2, 0x4), (0xF, 0x1)]); }),
+        case("DRW with collision", 0xD122, |s| {
+            sprite(s);
+            regs(s, &[(0x1, 0x8), (0x2, 0x4)]);
+            s.screen.insert(5, row(&[8, 9]));
+        }),
+        case("DRW past the right edge", 0xD122, |s| { sprite(s); regs(s, &[(0x1, 60), (0x2, 0x0)]); }),
+        case("DRW past the bottom edge", 0xD122, |s| { sprite(s); regs(s, &[(0x1, 0x0), (0x2, 31)]); }),
+        case("DRW starting off screen", 0xD122, |s| { sprite(s); regs(s, &[(0x1, 70), (0x2, 33)]); }),
+        case("DRW with VF as a coordinate", 0xD1F2, |s| { sprite(s); regs(s, &[(0x1, 0x0), (0xF, 0x3)]); }),
+        case("DRW with no rows", 0xD120, |s| { sprite(s); regs(s, &[(0xF, 0x1)]); }),
+        case("DRW before the vblank", 0xD122, |s| { sprite(s); s.vblank = false; }),
+        case("SKP pressed", 0xE59E, |s| { regs(s, &[(0x5, 0xA)]); s.key = Some(0xA); }),
+        case("SKP other key", 0xE59E, |s| { regs(s, &[(0x5, 0xA)]); s.key = Some(0xB); }),
+        case("SKP nothing held", 0xE59E, |s| regs(s, &[(0x5, 0xA)])),
+        case("SKNP pressed", 0xE5A1, |s| { regs(s, &[(0x5, 0xA)]); s.key = Some(0xA); }),
+        case("SKNP nothing held", 0xE5A1, |s| regs(s, &[(0x5, 0xA)])),
+        case("LD Vx, DT", 0xF207, |s| s.dt = 0x3C),
+        case("LD Vx, K waiting", 0xF20A, |_| ()),
+        case("LD Vx, K pressed", 0xF20A, |s| s.key = Some(0xE)),
+        case("LD DT, Vx", 0xF215, |s| regs(s, &[(0x2, 0x3C)])),
+        case("LD ST, Vx", 0xF218, |s| regs(s, &[(0x2, 0x3C)])),
+        case("ADD I, Vx", 0xF21E, |s| { s.i = 0x300; regs(s, &[(0x2, 0x10), (0xF, 0x1)]); }),
+        case("ADD I, Vx past 0xFFF", 0xF21E, |s| { s.i = 0xFFF; regs(s, &[(0x2, 0x02)]); }),
+        case("LD F, Vx", 0xF229, |s| regs(s, &[(0x2, 0xA)])),
+        case("LD F, Vx ignores the high nibble", 0xF229, |s| regs(s, &[(0x2, 0x1F)])),
+        case("LD B, Vx", 0xF233, |s| { s.i = 0x300; regs(s, &[(0x2, 137)]); memory(s, 0x300, &[0xFF; 3]); }),
+        case("LD B, Vx zero", 0xF233, |s| { s.i = 0x300; memory(s, 0x300, &[0xFF; 3]); }),
+        case("LD B, Vx 255", 0xF233, |s| { s.i = 0x300; regs(s, &[(0x2, 255)]); memory(s, 0x300, &[0x0; 3]); }),
+        case("LD [I], V0", 0xF055, |s| { s.i = 0x300; regs(s, &[(0x0, 0x12)]); memory(s, 0x300, &[0x0; 2]); }),
+        case("LD [I], Vx", 0xF355, |s| {
+            s.i = 0x300;
+            regs(s, &[(0x0, 0x1), (0x1, 0x2), (0x2, 0x3), (0x3, 0x4), (0x4, 0x5)]);
+            memory(s, 0x300, &[0x0; 5]);
+        }),
+        case("LD V0, [I]", 0xF065, |s| { s.i = 0x300; memory(s, 0x300, &[0x12, 0x34]); }),
+        case("LD Vx, [I]", 0xF365, |s| { s.i = 0x300; regs(s, &[(0x4, 0x55)]); memory(s, 0x300, &[0x1, 0x2, 0x3, 0x4, 0x5]); })
+    ]
+}
+
+fn main() {
+    let mut vectors: Vec<TestVector> = Vec::new();
+
+    for case in cases() {
+        let mut outcomes: Vec<TestVector> = Vec::new();
+
+        for profile in profiles() {
+            let mut vector = TestVector {
+                name: case.name.to_string(),
+                opcode: case.opcode,
+                profiles: vec![profile.to_string()],
+                initial: case.initial.clone(),
+                expected: Snapshot::default()
+            };
+            let quirks = profile_quirks(profile).expect("profiles() names valid profiles");
+            vector.expected = vector.run(quirks).unwrap_or_else(|e| panic!("{}", e));
+            if let Some((what, check)) = case.requires {
+                assert!(check(&vector.expected), "{} under {} should leave {}", case.name, profile, what);
+            }
+
+            match outcomes.iter_mut().find(|outcome| outcome.expected == vector.expected) {
+                Some(outcome) => outcome.profiles.push(profile.to_string()),
+                None => outcomes.push(vector)
+            }
+        }
+        vectors.extend(outcomes);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&vectors).expect("vectors serialize"));
+}
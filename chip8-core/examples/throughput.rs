@@ -0,0 +1,65 @@
+//! Measures instructions per second on the bundled ROMs in the interpreter, with the decode
+//! cache and in the recompiler:
+//!
+//!     cargo run --release -p chip8-core --example throughput [instructions per ROM]
+
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+use chip8_core::cart::Cartridge;
+use chip8_core::machine::{Machine, MachineBuilder};
+
+const DEFAULT_INSTRUCTIONS: u64 = 5_000_000;
+/// Instructions between 60Hz ticks, so timers run down and ROMs waiting on them move on.
+const TICKRATE: u64 = 1000;
+
+/// Runs a ROM for `instructions` instructions or until it faults, returning the instructions
+/// per second.
+fn ips(cart: &Cartridge, builder: fn(MachineBuilder) -> MachineBuilder, instructions: u64) -> f64 {
+    let keypad = Rc::new(Cell::new(Some(0x0)));
+    let mut machine = builder(Machine::builder(cart).seed(Some(0)).keypad(keypad)).build()
+        .expect("a cartridge always fits at 0x200");
+
+    let start = Instant::now();
+    let mut executed = 0;
+    while executed < instructions {
+        let mut ran = 0;
+        let result = machine.run(TICKRATE as u32, |_, _| ran += 1);
+        executed += ran;
+        if result.is_err() {
+            break;
+        }
+        machine.state.clock_tick(1);
+    }
+    executed as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let instructions = env::args().nth(1)
+        .map(|n| n.parse().expect("instructions is a number"))
+        .unwrap_or(DEFAULT_INSTRUCTIONS);
+
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut paths: Vec<_> = fs::read_dir(&roms).expect("roms directory")
+        .map(|entry| entry.expect("roms directory entry").path())
+        .collect();
+    paths.sort();
+
+    println!("{:<20} {:>14} {:>14} {:>14}", "ROM", "interpreter", "decode cache", "recompiler");
+    for path in paths {
+        let cart = match Cartridge::load(&mut fs::File::open(&path).unwrap()) {
+            Ok(cart) => cart,
+            Err(_) => continue
+        };
+
+        let interpreter = ips(&cart, |builder| builder, instructions);
+        let cached = ips(&cart, |builder| builder.decode_cache(true), instructions);
+        let recompiled = ips(&cart, |builder| builder.recompiler(true), instructions);
+        println!("{:<20} {:>14.0} {:>14.0} {:>14.0}",
+                 path.file_name().unwrap().to_string_lossy(), interpreter, cached, recompiled);
+    }
+}
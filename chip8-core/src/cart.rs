@@ -1,18 +1,355 @@
-use std::io::Read;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
 
-const MAX_CART_SIZE: usize = 3584;
+use crate::sha1::sha1_hex;
+
+pub const MAX_CART_SIZE: usize = 3584;
+/// How much past `MAX_CART_SIZE` is read to report a ROM's size, so endless input such as
+/// `/dev/zero` cannot hang loading.
+const MAX_OVERFLOW: usize = 4096;
 
 pub struct Cartridge {
     pub buffer: [u8; MAX_CART_SIZE],
     pub size: usize
 }
 
+#[derive(Debug)]
+pub enum CartError {
+    /// The `RomInput` failed, e.g. with an `io::Error`.
+    Io(Box<dyn Error + Send + Sync>),
+    Empty,
+    /// The ROM is `size` bytes, or at least that many if `at_least` is set because reading
+    /// stopped before the end.
+    TooLarge { size: usize, at_least: bool },
+    Format(String),
+    Archive(String)
+}
+
+impl Display for CartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CartError::Io(e) => write!(f, "failed to read ROM: {}", e),
+            CartError::Empty => write!(f, "ROM is empty"),
+            CartError::TooLarge{size, at_least} => {
+                let at_least = if *at_least { "at least " } else { "" };
+                write!(f, "ROM is {}{} bytes, the maximum is {} bytes", at_least, size, MAX_CART_SIZE)
+            },
+            CartError::Format(msg) => write!(f, "malformed ROM: {}", msg),
+            CartError::Archive(msg) => write!(f, "failed to read archive: {}", msg)
+        }
+    }
+}
+
+impl Error for CartError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CartError::Io(e) => Some(e.as_ref()),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for CartError {
+    fn from(e: io::Error) -> Self {
+        CartError::Io(Box::new(e))
+    }
+}
+
+/// Somewhere a ROM is read from: any `io::Read` with std, byte slices without.
+pub trait RomInput {
+    /// Reads some bytes into `buf`, returning how many, or 0 at the end of the ROM.
+    fn read_rom(&mut self, buf: &mut [u8]) -> Result<usize, CartError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read + ?Sized> RomInput for R {
+    fn read_rom(&mut self, buf: &mut [u8]) -> Result<usize, CartError> {
+        loop {
+            match self.read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                result => return Ok(result?)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl RomInput for &[u8] {
+    fn read_rom(&mut self, buf: &mut [u8]) -> Result<usize, CartError> {
+        let n = buf.len().min(self.len());
+        let (read, rest) = self.split_at(n);
+        buf[.. n].copy_from_slice(read);
+        *self = rest;
+        Ok(n)
+    }
+}
+
 impl Cartridge {
-    pub fn load(reader: &mut dyn Read) -> Cartridge {
+    /// Reads the whole ROM from `reader`, retrying short reads until EOF. Reading a ROM that is
+    /// too large stops a few KB past the maximum.
+    pub fn load<R: RomInput + ?Sized>(reader: &mut R) -> Result<Cartridge, CartError> {
+        let mut buffer = [0u8; MAX_CART_SIZE];
+        let mut size = 0;
+
+        while size < MAX_CART_SIZE {
+            match reader.read_rom(&mut buffer[size..])? {
+                0 => break,
+                n => size += n
+            }
+        }
+
+        if size == MAX_CART_SIZE {
+            let mut overflow = 0;
+            let mut scratch = [0u8; 256];
+            while overflow < MAX_OVERFLOW {
+                match reader.read_rom(&mut scratch)? {
+                    0 => break,
+                    n => overflow += n
+                }
+            }
+            if overflow > 0 {
+                return Err(CartError::TooLarge { size: size + overflow, at_least: overflow >= MAX_OVERFLOW });
+            }
+        }
+
+        if size == 0 {
+            return Err(CartError::Empty);
+        }
+
+        Ok(Cartridge { buffer, size })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cartridge, CartError> {
+        if bytes.is_empty() {
+            return Err(CartError::Empty);
+        }
+        if bytes.len() > MAX_CART_SIZE {
+            return Err(CartError::TooLarge { size: bytes.len(), at_least: false });
+        }
+
         let mut buffer = [0u8; MAX_CART_SIZE];
-        match reader.read(&mut buffer) {
-            Ok(size) => Cartridge { buffer, size },
-            Err(_) => panic!("Failed to read!")
+        buffer[.. bytes.len()].copy_from_slice(bytes);
+        Ok(Cartridge { buffer, size: bytes.len() })
+    }
+
+    /// Parses a text dump of whitespace-separated hex bytes or words (`00E0 a2 2a 0x60`).
+    /// Anything after `#` or `;` on a line is a comment.
+    pub fn from_hex_text(text: &str) -> Result<Cartridge, CartError> {
+        let mut bytes = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            for token in line.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+                let digits = token.trim_start_matches("0x").trim_start_matches("0X");
+                if digits.is_empty() || digits.len() % 2 != 0 || !digits.is_ascii() {
+                    return Err(CartError::Format(format!("line {}: invalid hex value {}", n + 1, token)));
+                }
+                for i in (0 .. digits.len()).step_by(2) {
+                    let byte = u8::from_str_radix(&digits[i .. i + 2], 16)
+                        .map_err(|_| CartError::Format(format!("line {}: invalid hex value {}", n + 1, token)))?;
+                    bytes.push(byte);
+                }
+            }
         }
+
+        Cartridge::from_bytes(&bytes)
+    }
+
+    /// Parses an Intel HEX file. The lowest address in the file is taken as the start of the ROM
+    /// and gaps are zero filled.
+    pub fn from_intel_hex(text: &str) -> Result<Cartridge, CartError> {
+        let mut records: Vec<(u32, Vec<u8>)> = Vec::new();
+        let mut base: u32 = 0;
+
+        for (n, line) in text.lines().map(str::trim).enumerate().filter(|(_, l)| !l.is_empty()) {
+            let err = |msg: &str| CartError::Format(format!("line {}: {}", n + 1, msg));
+
+            let digits = line.strip_prefix(':').ok_or_else(|| err("record does not start with ':'"))?;
+            if digits.len() < 10 || digits.len() % 2 != 0 || !digits.is_ascii() {
+                return Err(err("truncated record"));
+            }
+            let record = (0 .. digits.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i .. i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| err("invalid hex digit"))?;
+
+            let len = record[0] as usize;
+            if record.len() != len + 5 {
+                return Err(err("record length does not match its byte count"));
+            }
+            if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+                return Err(err("checksum mismatch"));
+            }
+
+            let addr = (record[1] as u32) << 8 | record[2] as u32;
+            let data = &record[4 .. 4 + len];
+            match record[3] {
+                0x00 => records.push((base + addr, data.to_vec())),
+                0x01 => break,
+                0x02 if len == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 4,
+                0x04 if len == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 16,
+                0x03 | 0x05 => (),
+                _ => return Err(err("unsupported record type"))
+            }
+        }
+
+        // Records can run past 4GB at the very top of the address space
+        let start = records.iter().map(|(addr, _)| *addr as u64).min().unwrap_or(0);
+        let end = records.iter().map(|(addr, data)| *addr as u64 + data.len() as u64).max().unwrap_or(0);
+        if (end - start) as usize > MAX_CART_SIZE {
+            return Err(CartError::TooLarge { size: (end - start) as usize, at_least: false });
+        }
+
+        let mut bytes = vec![0u8; (end - start) as usize];
+        for (addr, data) in records {
+            let offset = (addr as u64 - start) as usize;
+            bytes[offset .. offset + data.len()].copy_from_slice(&data);
+        }
+
+        Cartridge::from_bytes(&bytes)
+    }
+
+    /// Lower-case hex SHA-1 of the ROM image, as used by ROM databases.
+    pub fn sha1(&self) -> String {
+        sha1_hex(&self.buffer[.. self.size])
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Read;
+
+    use super::*;
+
+    /// Hands out at most one byte per `read` call, like a slow pipe.
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl<'a> Read for TrickleReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                },
+                _ => Ok(0)
+            }
+        }
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    #[test]
+    pub fn load_reads_until_eof() {
+        let rom = [0xAB; 100];
+
+        let cart = Cartridge::load(&mut TrickleReader(&rom)).unwrap();
+
+        assert_eq!(cart.size, 100);
+        assert_eq!(&cart.buffer[..100], &rom[..]);
+    }
+
+    #[test]
+    pub fn load_accepts_rom_of_maximum_size() {
+        let rom = [0x1; MAX_CART_SIZE];
+
+        let cart = Cartridge::load(&mut &rom[..]).unwrap();
+
+        assert_eq!(cart.size, MAX_CART_SIZE);
+    }
+
+    #[test]
+    pub fn load_rejects_empty_rom() {
+        let result = Cartridge::load(&mut io::empty());
+
+        assert!(matches!(result, Err(CartError::Empty)));
+    }
+
+    #[test]
+    pub fn load_rejects_oversized_rom_and_reports_size() {
+        let rom = [0x1; MAX_CART_SIZE + 10];
+
+        let result = Cartridge::load(&mut &rom[..]);
+
+        assert!(matches!(result, Err(CartError::TooLarge { size, at_least: false }) if size == MAX_CART_SIZE + 10));
+    }
+
+    #[test]
+    pub fn load_gives_up_on_endless_input() {
+        let result = Cartridge::load(&mut io::repeat(0x1));
+
+        match result {
+            Err(error @ CartError::TooLarge { at_least: true, .. }) => {
+                assert!(error.to_string().starts_with("ROM is at least "));
+            },
+            _ => panic!("endless input was not rejected as too large")
+        }
+    }
+
+    #[test]
+    pub fn sha1_covers_only_rom_contents() {
+        let cart = Cartridge::load(&mut &b"abc"[..]).unwrap();
+
+        assert_eq!(cart.sha1(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    pub fn hex_text_accepts_bytes_words_and_comments() {
+        let text = "00E0 a2 2a # clear and load I\n0x60,0x0C ; done\n";
+
+        let cart = Cartridge::from_hex_text(text).unwrap();
+
+        assert_eq!(&cart.buffer[.. cart.size], &[0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C]);
+    }
+
+    #[test]
+    pub fn hex_text_rejects_odd_digits() {
+        assert!(matches!(Cartridge::from_hex_text("00E"), Err(CartError::Format(_))));
+    }
+
+    #[test]
+    pub fn intel_hex_is_placed_relative_to_lowest_address() {
+        let text = ":0202000000E01C\n:02020400A22A2C\n:00000001FF\n";
+
+        let cart = Cartridge::from_intel_hex(text).unwrap();
+
+        assert_eq!(&cart.buffer[.. cart.size], &[0x00, 0xE0, 0x00, 0x00, 0xA2, 0x2A]);
+    }
+
+    #[test]
+    pub fn intel_hex_rejects_bad_checksum() {
+        let result = Cartridge::from_intel_hex(":0202000000E01D\n");
+
+        assert!(matches!(result, Err(CartError::Format(_))));
+    }
+
+    #[test]
+    pub fn intel_hex_accepts_records_at_the_top_of_the_address_space() {
+        let cart = Cartridge::from_intel_hex(":02000004FFFFFC\n:02FFFF0000E020\n").unwrap();
+
+        assert_eq!(&cart.buffer[.. cart.size], &[0x00, 0xE0]);
+    }
+
+    #[test]
+    pub fn load_reports_io_errors() {
+        let result = Cartridge::load(&mut FailingReader);
+
+        assert!(matches!(result, Err(CartError::Io(_))));
+    }
+}
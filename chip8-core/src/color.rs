@@ -0,0 +1,79 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+    pub const WHITE: Rgb = Rgb { r: 255, g: 255, b: 255 };
+
+    /// Blends towards `other` by `amount` out of 255.
+    pub fn mix(&self, other: Rgb, amount: u8) -> Rgb {
+        let channel = |from: u8, to: u8| {
+            ((from as u32 * (255 - amount as u32) + to as u32 * amount as u32 + 127) / 255) as u8
+        };
+        Rgb { r: channel(self.r, other.r), g: channel(self.g, other.g), b: channel(self.b, other.b) }
+    }
+}
+
+impl Display for Rgb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses `#rrggbb`, with or without the leading `#`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(format!("invalid colour: {}", s));
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i .. i + 2], 16)
+            .map_err(|_| format!("invalid colour: {}", s));
+        Ok(Rgb { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parses_hex_colours() {
+        assert_eq!("#ff8000".parse::<Rgb>(), Ok(Rgb { r: 255, g: 128, b: 0 }));
+        assert_eq!("00FF00".parse::<Rgb>(), Ok(Rgb { r: 0, g: 255, b: 0 }));
+    }
+
+    #[test]
+    pub fn rejects_malformed_colours() {
+        assert!("#fff".parse::<Rgb>().is_err());
+        assert!("#gg0000".parse::<Rgb>().is_err());
+    }
+
+    #[test]
+    pub fn mix_blends_between_colours() {
+        let red = Rgb { r: 255, g: 0, b: 0 };
+
+        assert_eq!(Rgb::BLACK.mix(red, 0), Rgb::BLACK);
+        assert_eq!(Rgb::BLACK.mix(red, 255), red);
+        assert_eq!(Rgb::BLACK.mix(red, 128), Rgb { r: 128, g: 0, b: 0 });
+    }
+
+    #[test]
+    pub fn display_round_trips() {
+        let colour = Rgb { r: 1, g: 2, b: 3 };
+        assert_eq!(colour.to_string().parse::<Rgb>(), Ok(colour));
+    }
+}
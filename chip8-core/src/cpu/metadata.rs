@@ -0,0 +1,302 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+
+use crate::cpu::opcodes::Opcode;
+use crate::quirks::Platform;
+
+/// A set of V registers.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct Registers(u16);
+
+impl Registers {
+    pub const NONE: Registers = Registers(0x0);
+
+    pub fn of(regs: &[u8]) -> Registers {
+        Registers(regs.iter().fold(0x0, |set, x| set | 0x1 << (x & 0xF)))
+    }
+
+    /// V0 to Vx.
+    pub fn up_to(x: u8) -> Registers {
+        Registers((0x1u32 << ((x & 0xF) + 1)).wrapping_sub(1) as u16)
+    }
+
+    pub fn contains(&self, x: u8) -> bool {
+        x < 16 && self.0 & 0x1 << x != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0x0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0 .. 16).filter(move |x| self.contains(*x))
+    }
+
+    fn with(self, other: Registers) -> Registers {
+        Registers(self.0 | other.0)
+    }
+}
+
+impl Display for Registers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let regs: Vec<String> = self.iter().map(|x| format!("V{:X}", x)).collect();
+        write!(f, "{}", regs.join(", "))
+    }
+}
+
+/// Where execution goes after an instruction.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Flow {
+    Next,
+    /// The next instruction may be skipped.
+    Skip,
+    Jump(u16),
+    /// A jump to the address plus a register.
+    IndirectJump(u16),
+    Call(u16),
+    Return,
+    /// The instruction repeats until a key is pressed.
+    Wait,
+    /// The instruction cannot be executed and stops the machine.
+    Fault
+}
+
+/// Memory accessed through I, starting at I.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct MemoryAccess {
+    pub write: bool,
+    pub len: u16
+}
+
+const ALL: &[Platform] = &Platform::ALL;
+const VIP: &[Platform] = &[Platform::OriginalChip8, Platform::HybridVip];
+const SUPER_CHIP: &[Platform] = &[Platform::SuperChip1, Platform::SuperChip, Platform::XoChip];
+const XO_CHIP: &[Platform] = &[Platform::XoChip];
+const NONE: &[Platform] = &[];
+
+/// Facts about instructions as far as they are known without running them. Where quirks
+/// change the behaviour, the answers cover every platform.
+impl Opcode {
+    /// The V registers the instruction may read.
+    pub fn reads(&self) -> Registers {
+        match *self {
+            Opcode::SEVxByte{x, ..} | Opcode::SNEVxByte{x, ..} | Opcode::ADDVxByte{x, ..} |
+            Opcode::SKPVx{x} | Opcode::SKNPVx{x} | Opcode::LDDTVx{x} | Opcode::LDSTVx{x} |
+            Opcode::ADDIVx{x} | Opcode::LDFVx{x} | Opcode::LDBVx{x} => Registers::of(&[x]),
+            Opcode::SEVxVy{x, y} | Opcode::SNEVxVy{x, y} | Opcode::ORVxVy{x, y} | Opcode::ANDVxVy{x, y} |
+            Opcode::XORVxVy{x, y} | Opcode::ADDVxVy{x, y} | Opcode::SUBVxVy{x, y} | Opcode::SHRVxVy{x, y} |
+            Opcode::SUBNVxVy{x, y} | Opcode::SHLVxVy{x, y} | Opcode::DRW{x, y, ..} => Registers::of(&[x, y]),
+            Opcode::LDVxVy{y, ..} => Registers::of(&[y]),
+            // V0, or Vx from the address with the jump quirk
+            Opcode::JPV0Addr{addr} => Registers::of(&[0x0, (addr >> 8) as u8]),
+            Opcode::LDIVx{x} => Registers::up_to(x),
+            _ => Registers::NONE
+        }
+    }
+
+    /// The V registers the instruction may write, including VF when used as a flag.
+    pub fn writes(&self) -> Registers {
+        let vf = Registers::of(&[0xF]);
+        match *self {
+            Opcode::LDVxByte{x, ..} | Opcode::ADDVxByte{x, ..} | Opcode::LDVxVy{x, ..} |
+            Opcode::RNDVxByte{x, ..} | Opcode::LDVxDT{x} | Opcode::LDVxK{x} => Registers::of(&[x]),
+            // VF is cleared by the logic quirk
+            Opcode::ORVxVy{x, ..} | Opcode::ANDVxVy{x, ..} | Opcode::XORVxVy{x, ..} |
+            Opcode::ADDVxVy{x, ..} | Opcode::SUBVxVy{x, ..} | Opcode::SHRVxVy{x, ..} |
+            Opcode::SUBNVxVy{x, ..} | Opcode::SHLVxVy{x, ..} => Registers::of(&[x]).with(vf),
+            Opcode::DRW{..} => vf,
+            Opcode::LDVxI{x} => Registers::up_to(x),
+            _ => Registers::NONE
+        }
+    }
+
+    /// Whether VF may be changed, as a result or as a flag.
+    pub fn modifies_vf(&self) -> bool {
+        self.writes().contains(0xF)
+    }
+
+    pub fn reads_i(&self) -> bool {
+        matches!(self,
+            Opcode::ADDIVx{..} | Opcode::DRW{..} | Opcode::LDBVx{..} | Opcode::LDIVx{..} | Opcode::LDVxI{..})
+    }
+
+    /// Whether I may change. `LD [I], Vx` and `LD Vx, [I]` advance it unless a quirk says otherwise.
+    pub fn writes_i(&self) -> bool {
+        matches!(self,
+            Opcode::LDIAddr{..} | Opcode::ADDIVx{..} | Opcode::LDFVx{..} | Opcode::LDIVx{..} | Opcode::LDVxI{..})
+    }
+
+    /// The memory read or written through I, if any.
+    pub fn memory(&self) -> Option<MemoryAccess> {
+        match *self {
+            Opcode::DRW{nibble, ..} => Some(MemoryAccess { write: false, len: nibble as u16 }),
+            Opcode::LDBVx{..} => Some(MemoryAccess { write: true, len: 3 }),
+            Opcode::LDIVx{x} => Some(MemoryAccess { write: true, len: x as u16 + 1 }),
+            Opcode::LDVxI{x} => Some(MemoryAccess { write: false, len: x as u16 + 1 }),
+            _ => None
+        }
+    }
+
+    pub fn flow(&self) -> Flow {
+        match *self {
+            Opcode::RET => Flow::Return,
+            Opcode::JP{addr} => Flow::Jump(addr),
+            Opcode::CALL{addr} => Flow::Call(addr),
+            Opcode::JPV0Addr{addr} => Flow::IndirectJump(addr),
+            Opcode::SEVxByte{..} | Opcode::SNEVxByte{..} | Opcode::SEVxVy{..} |
+            Opcode::SNEVxVy{..} | Opcode::SKPVx{..} | Opcode::SKNPVx{..} => Flow::Skip,
+            Opcode::LDVxK{..} => Flow::Wait,
+            Opcode::UNKNOWN{..} => Flow::Fault,
+            _ => Flow::Next
+        }
+    }
+
+    /// Whether execution may continue anywhere but the next instruction.
+    pub fn changes_flow(&self) -> bool {
+        self.flow() != Flow::Next
+    }
+
+    /// Typical machine cycles (8 clocks each, about 4.5µs) the COSMAC VIP interpreter spends on
+    /// the instruction. `DRW` includes waiting for the display interrupt, and `LD Vx, K` is
+    /// one check of the keypad. `timing::cycles` times `DRW` by the sprite instead.
+    pub fn vip_cycles(&self) -> u32 {
+        match self {
+            Opcode::CLS => 24,
+            Opcode::RET | Opcode::JP{..} | Opcode::CALL{..} | Opcode::JPV0Addr{..} => 23,
+            Opcode::SEVxByte{..} | Opcode::SNEVxByte{..} | Opcode::LDIAddr{..} => 12,
+            Opcode::SEVxVy{..} | Opcode::SNEVxVy{..} | Opcode::SKPVx{..} | Opcode::SKNPVx{..} => 16,
+            Opcode::LDVxByte{..} => 6,
+            Opcode::ADDVxByte{..} | Opcode::LDVxDT{..} | Opcode::LDVxK{..} |
+            Opcode::LDDTVx{..} | Opcode::LDSTVx{..} => 10,
+            Opcode::LDVxVy{..} | Opcode::ORVxVy{..} | Opcode::ANDVxVy{..} | Opcode::XORVxVy{..} |
+            Opcode::ADDVxVy{..} | Opcode::SUBVxVy{..} | Opcode::SHRVxVy{..} | Opcode::SUBNVxVy{..} |
+            Opcode::SHLVxVy{..} => 44,
+            Opcode::RNDVxByte{..} => 36,
+            Opcode::DRW{..} => 5004,
+            Opcode::ADDIVx{..} => 19,
+            Opcode::LDFVx{..} => 20,
+            Opcode::LDBVx{..} => 204,
+            Opcode::LDIVx{..} | Opcode::LDVxI{..} => 133,
+            Opcode::UNKNOWN{..} => 0
+        }
+    }
+
+    /// The platforms that have the instruction. Words this emulator does not decode may still
+    /// be machine code calls on the VIP or SUPER-CHIP and XO-CHIP extensions.
+    pub fn platforms(&self) -> &'static [Platform] {
+        match *self {
+            Opcode::UNKNOWN{opcode} => match opcode {
+                (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB ..= 0xF) => SUPER_CHIP,
+                (0xF, _, 0x3, 0x0) | (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => SUPER_CHIP,
+                (0x0, 0x0, 0xD, _) | (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => XO_CHIP,
+                (0xF, 0x0, 0x0, 0x0) | (0xF, _, 0x0, 0x1) | (0xF, 0x0, 0x0, 0x2) | (0xF, _, 0x3, 0xA) => XO_CHIP,
+                (0x0, _, _, _) => VIP,
+                _ => NONE
+            },
+            _ => ALL
+        }
+    }
+
+    /// A one-line description of what the instruction does.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Opcode::CLS => "Clear the display",
+            Opcode::RET => "Return from a subroutine",
+            Opcode::JP{..} => "Jump to addr",
+            Opcode::CALL{..} => "Call the subroutine at addr",
+            Opcode::SEVxByte{..} => "Skip the next instruction if Vx equals byte",
+            Opcode::SNEVxByte{..} => "Skip the next instruction if Vx does not equal byte",
+            Opcode::SEVxVy{..} => "Skip the next instruction if Vx equals Vy",
+            Opcode::LDVxByte{..} => "Set Vx to byte",
+            Opcode::ADDVxByte{..} => "Add byte to Vx, leaving VF alone",
+            Opcode::LDVxVy{..} => "Set Vx to Vy",
+            Opcode::ORVxVy{..} => "Set Vx to Vx OR Vy",
+            Opcode::ANDVxVy{..} => "Set Vx to Vx AND Vy",
+            Opcode::XORVxVy{..} => "Set Vx to Vx XOR Vy",
+            Opcode::ADDVxVy{..} => "Add Vy to Vx, VF is the carry",
+            Opcode::SUBVxVy{..} => "Subtract Vy from Vx, VF is 1 unless it borrows",
+            Opcode::SHRVxVy{..} => "Shift Vy (or Vx) right into Vx, VF is the bit shifted out",
+            Opcode::SUBNVxVy{..} => "Set Vx to Vy minus Vx, VF is 1 unless it borrows",
+            Opcode::SHLVxVy{..} => "Shift Vy (or Vx) left into Vx, VF is the bit shifted out",
+            Opcode::SNEVxVy{..} => "Skip the next instruction if Vx does not equal Vy",
+            Opcode::LDIAddr{..} => "Set I to addr",
+            Opcode::JPV0Addr{..} => "Jump to addr plus V0 (or Vx)",
+            Opcode::RNDVxByte{..} => "Set Vx to a random number AND byte",
+            Opcode::DRW{..} => "Draw the sprite at I at (Vx, Vy), VF is set on collision",
+            Opcode::SKPVx{..} => "Skip the next instruction if the key in Vx is held",
+            Opcode::SKNPVx{..} => "Skip the next instruction unless the key in Vx is held",
+            Opcode::LDVxDT{..} => "Set Vx to the delay timer",
+            Opcode::LDVxK{..} => "Wait for a key press and store the key in Vx",
+            Opcode::LDDTVx{..} => "Set the delay timer to Vx",
+            Opcode::LDSTVx{..} => "Set the sound timer to Vx",
+            Opcode::ADDIVx{..} => "Add Vx to I",
+            Opcode::LDFVx{..} => "Point I at the hex digit glyph for Vx",
+            Opcode::LDBVx{..} => "Store the decimal digits of Vx at I, I+1 and I+2",
+            Opcode::LDIVx{..} => "Store V0 to Vx in memory starting at I",
+            Opcode::LDVxI{..} => "Load V0 to Vx from memory starting at I",
+            Opcode::UNKNOWN{opcode} => match opcode {
+                (0x0, 0x0, 0xC, _) => "Scroll the display down (SUPER-CHIP)",
+                (0x0, 0x0, 0xD, _) => "Scroll the display up (XO-CHIP)",
+                (0x0, 0x0, 0xF, 0xB) => "Scroll the display right (SUPER-CHIP)",
+                (0x0, 0x0, 0xF, 0xC) => "Scroll the display left (SUPER-CHIP)",
+                (0x0, 0x0, 0xF, 0xD) => "Exit the interpreter (SUPER-CHIP)",
+                (0x0, 0x0, 0xF, 0xE) => "Switch to low resolution (SUPER-CHIP)",
+                (0x0, 0x0, 0xF, 0xF) => "Switch to high resolution (SUPER-CHIP)",
+                (0x0, _, _, _) => "Call machine code (COSMAC VIP)",
+                (0x5, _, _, 0x2) => "Store Vx to Vy in memory starting at I (XO-CHIP)",
+                (0x5, _, _, 0x3) => "Load Vx to Vy from memory starting at I (XO-CHIP)",
+                (0xF, 0x0, 0x0, 0x0) => "Set I to the following 16-bit address (XO-CHIP)",
+                (0xF, _, 0x0, 0x1) => "Select the drawing planes (XO-CHIP)",
+                (0xF, 0x0, 0x0, 0x2) => "Load the audio pattern at I (XO-CHIP)",
+                (0xF, _, 0x3, 0x0) => "Point I at the large hex digit glyph for Vx (SUPER-CHIP)",
+                (0xF, _, 0x3, 0xA) => "Set the audio pitch to Vx (XO-CHIP)",
+                (0xF, _, 0x7, 0x5) => "Save V0 to Vx to the flag registers (SUPER-CHIP)",
+                (0xF, _, 0x8, 0x5) => "Restore V0 to Vx from the flag registers (SUPER-CHIP)",
+                _ => "Not an instruction"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::opcodes::get_opcode;
+
+    use super::*;
+
+    #[test]
+    pub fn register_sets_list_their_members() {
+        assert_eq!(Registers::up_to(0x2).to_string(), "V0, V1, V2");
+        assert_eq!(Registers::up_to(0xF), Registers::of(&(0 .. 16).collect::<Vec<_>>()));
+        assert!(Registers::NONE.is_empty());
+    }
+
+    #[test]
+    pub fn metadata_describes_register_and_memory_use() {
+        let add = get_opcode(0x8124);
+        assert_eq!((add.reads(), add.writes()), (Registers::of(&[0x1, 0x2]), Registers::of(&[0x1, 0xF])));
+        assert!(add.modifies_vf());
+        assert_eq!(add.flow(), Flow::Next);
+
+        let store = get_opcode(0xF355);
+        assert_eq!(store.reads(), Registers::up_to(0x3));
+        assert_eq!(store.memory(), Some(MemoryAccess { write: true, len: 4 }));
+        assert!(store.reads_i() && store.writes_i());
+        assert!(!store.modifies_vf());
+
+        assert_eq!(get_opcode(0x3A42).flow(), Flow::Skip);
+        assert_eq!(get_opcode(0x2345).flow(), Flow::Call(0x345));
+    }
+
+    #[test]
+    pub fn unknown_words_name_the_platforms_that_have_them() {
+        assert_eq!(get_opcode(0x00FF).platforms(), SUPER_CHIP);
+        assert_eq!(get_opcode(0x0123).platforms(), VIP);
+        assert_eq!(get_opcode(0xF002).platforms(), XO_CHIP);
+        assert!(get_opcode(0xE000).platforms().is_empty());
+        assert_eq!(get_opcode(0x00E0).platforms(), &Platform::ALL);
+    }
+}
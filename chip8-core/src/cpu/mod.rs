@@ -1,19 +1,75 @@
-use std::cell::Cell;
-use std::fmt::{Display, Formatter};
-use std::fmt;
-use std::rc::Rc;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+#[cfg(feature = "std")]
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::SCREEN_HEIGHT;
+use crate::font::SMALL_GLYPH_SIZE;
+use crate::quirks::Quirks;
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
 
 use self::opcodes::*;
 
+pub mod metadata;
 pub mod opcodes;
 
 pub const MAX_STACK_SIZE: usize = 16;
 pub const MAX_MEMORY_SIZE: usize = 4096;
 pub const STARTING_PROGRAM_COUNTER: u16 = 0x200;
 
+/// Why an instruction could not be executed. The instruction changes nothing, but the program
+/// counter has already moved past it if it was fetched, as in `Machine::try_step`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    UnknownOpcode(Opcode),
+    /// `RND` without a seed or an `Entropy` source, in a build without std and so without a
+    /// clock to fall back on.
+    NoRandomSource
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            Fault::NoRandomSource => write!(f, "RND without a seed or an entropy source")
+        }
+    }
+}
+
+impl Error for Fault {}
+
+/// Where `SKP`, `SKNP` and `LD Vx, K` read the keypad from. A `Cell` the host sets as keys
+/// go up and down is enough.
+pub trait KeyInput {
+    /// The key currently held down.
+    fn held(&self) -> Option<u8>;
+}
+
+impl KeyInput for Cell<Option<u8>> {
+    fn held(&self) -> Option<u8> {
+        self.get()
+    }
+}
+
+/// Where `RND` takes random bytes from when the machine is not seeded, such as a hardware
+/// random number generator.
+pub trait Entropy {
+    fn random_byte(&self) -> u8;
+}
+
+/// The address an access to `addr` lands on. Memory is mirrored past the end, so ROMs that run
+/// off it read and write from the start again rather than crashing the emulator.
+fn mem_addr(addr: usize) -> usize {
+    addr % MAX_MEMORY_SIZE
+}
+
 #[derive(Clone)]
 pub struct ProcState {
     pub mem: [u8; 4096],
@@ -24,11 +80,26 @@ pub struct ProcState {
     pub stack: [u16; MAX_STACK_SIZE],
     pub delay_t: u8,
     pub sound_t: u8,
-    pub io_queue: Rc<Cell<Option<u8>>>,
+    pub io_queue: Rc<dyn KeyInput>,
     pub video_buffer: [u64; SCREEN_HEIGHT],
-    pub clock: u64
+    /// Machine cycles run under VIP timing, see `MachineBuilder::vip_timing`.
+    pub clock: u64,
+    pub quirks: Quirks,
+    /// Where the small hex digit glyphs used by `LD F, Vx` start.
+    pub font_address: u16,
+    /// Set by every 60Hz tick and consumed by `DRW` when the vblank quirk is enabled.
+    pub vblank: bool,
+    /// State of the generator behind `RND`, or `None` to take random bytes from `entropy`.
+    pub rng: Option<u64>,
+    /// Where `RND` takes random bytes from when not seeded. Without one it uses the system
+    /// clock, or faults without std.
+    pub entropy: Option<Rc<dyn Entropy>>,
+    /// Instructions already decoded, by address, if enabled.
+    decode_cache: Option<Box<DecodeCache>>
 }
 
+type DecodeCache = [Option<Opcode>; MAX_MEMORY_SIZE];
+
 impl Display for ProcState {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "PC={:#05x} | SP={:#03x} | I={:#05x}", self.pc, self.sp, self.ireg)
@@ -36,11 +107,7 @@ impl Display for ProcState {
 }
 
 impl ProcState {
-    pub fn reset(self) -> Self {
-        ProcState::new([0x0; MAX_MEMORY_SIZE], self.io_queue)
-    }
-
-    pub fn new(mem: [u8; MAX_MEMORY_SIZE], io_queue: Rc<Cell<Option<u8>>>) -> Self {
+    pub fn new(mem: [u8; MAX_MEMORY_SIZE], io_queue: Rc<dyn KeyInput>) -> Self {
         ProcState {
             mem,
             vreg: [0x0; 16],
@@ -52,12 +119,53 @@ impl ProcState {
             sound_t: 0,
             io_queue,
             video_buffer: [0x0; SCREEN_HEIGHT],
-            clock: 0
+            clock: 0,
+            quirks: Quirks::default(),
+            font_address: 0x0,
+            vblank: false,
+            rng: None,
+            entropy: None,
+            decode_cache: None
         }
     }
 
+    /// Keeps every decoded instruction until the memory under it is written, so that loops
+    /// are decoded only once. Anything writing to `mem` directly must call
+    /// `invalidate_decode_cache` afterwards.
+    pub fn enable_decode_cache(&mut self) {
+        self.decode_cache = Some(Box::new([None; MAX_MEMORY_SIZE]));
+    }
+
+    pub fn decode_cache_enabled(&self) -> bool {
+        self.decode_cache.is_some()
+    }
+
+    /// Forgets all decoded instructions.
+    pub fn invalidate_decode_cache(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+    }
+
+    /// Writes a byte of memory, forgetting the instructions that overlap it.
+    fn write_mem(&mut self, addr: u16, value: u8) {
+        let addr = mem_addr(addr as usize);
+        self.mem[addr] = value;
+        if let Some(cache) = &mut self.decode_cache {
+            cache[addr] = None;
+            cache[mem_addr(addr + MAX_MEMORY_SIZE - 1)] = None;
+        }
+    }
+
+    /// Makes `RND` produce the same sequence every time for the same seed.
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift never leaves zero, so mix the seed first
+        let mixed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        self.rng = Some(mixed | 0x1);
+    }
+
     pub fn pop(&mut self) -> u16 {
-        let val = self.stack[self.sp as usize];
+        let val = self.stack[self.sp];
         let (wrapped_sp, overflowed) = self.sp.overflowing_sub(1);
 
         self.sp = wrapped_sp;
@@ -66,11 +174,11 @@ impl ProcState {
             panic!("Under-flowed stack: {}", &self);
         }
 
-        return val;
+        val
     }
 
     pub fn push(&mut self, val: u16) {
-        self.sp = self.sp + 1;
+        self.sp += 1;
 
         if self.sp > MAX_STACK_SIZE {
             panic!("Over-flowed stack: {}", &self)
@@ -79,33 +187,55 @@ impl ProcState {
         self.stack[self.sp] = val;
     }
 
+    /// The 60Hz tick: counts the timers down and signals the display interrupt.
     pub fn clock_tick(&mut self, _freq: u64) {
-        self.clock += 1;
-        self.delay_t = self.delay_t.checked_sub(1).unwrap_or(0);
-        self.sound_t = self.sound_t.checked_sub(1).unwrap_or(0);
+        self.delay_t = self.delay_t.saturating_sub(1);
+        self.sound_t = self.sound_t.saturating_sub(1);
+        self.vblank = true;
     }
 
     pub fn fetch_and_decode_opcode(&mut self) -> Opcode {
-        let high_byte: u8 = self.mem[self.pc as usize];
-        let low_byte: u8 = self.mem[(self.pc+1) as usize];
-        self.pc = self.pc + 2;
-        let opcode = (high_byte as u16) << 8 | (low_byte as u16);
+        let addr = mem_addr(self.pc as usize);
+        self.pc = self.pc.wrapping_add(2);
+        if let Some(opcode) = self.decode_cache.as_ref().and_then(|cache| cache[addr]) {
+            return opcode;
+        }
+
+        let high_byte: u8 = self.mem[addr];
+        let low_byte: u8 = self.mem[mem_addr(addr + 1)];
+        let opcode = get_opcode((high_byte as u16) << 8 | (low_byte as u16));
 
-        return get_opcode(opcode);
+        if let Some(cache) = &mut self.decode_cache {
+            cache[addr] = Some(opcode);
+        }
+        opcode
     }
 
+    /// Executes an instruction, panicking on a fault.
     pub fn execute_opcode(&mut self, op: Opcode) {
+        if let Err(fault) = self.try_execute_opcode(op) {
+            panic!("{}: {}", fault, &self);
+        }
+    }
+
+    pub fn try_execute_opcode(&mut self, op: Opcode) -> Result<(), Fault> {
         match op {
             Opcode::CLS => {
-
+                self.video_buffer = [0x0; SCREEN_HEIGHT];
             },
             Opcode::RET => {
+                if self.sp == 0 {
+                    return Err(Fault::StackUnderflow);
+                }
                 self.pc = self.pop();
             },
             Opcode::JP{addr} => {
                 self.pc = addr;
             },
             Opcode::CALL{addr} => {
+                if self.sp + 1 >= MAX_STACK_SIZE {
+                    return Err(Fault::StackOverflow);
+                }
                 let cur_pc = self.pc; self.push(cur_pc); self.pc = addr;
             },
             Opcode::SEVxByte{x, byte} => {
@@ -134,7 +264,8 @@ impl ProcState {
                 self.vreg[x as usize] = self.vreg[y as usize];
             },
             Opcode::ORVxVy{x, y} => {
-                self.vreg[x as usize] = self.vreg[x as usize] | self.vreg[y as usize];
+                self.vreg[x as usize] |= self.vreg[y as usize];
+                self.logic_quirk();
             },
             Opcode::ADDVxVy{x, y} => {
                 let (val, overflowed) = self.vreg[x as usize].overflowing_add(self.vreg[y as usize]);
@@ -142,28 +273,32 @@ impl ProcState {
                 self.vreg[0xF] = if overflowed { 1 } else { 0 };
             },
             Opcode::XORVxVy{x, y} => {
-                self.vreg[x as usize] = self.vreg[x as usize] ^ self.vreg[y as usize];
+                self.vreg[x as usize] ^= self.vreg[y as usize];
+                self.logic_quirk();
             }
             Opcode::ANDVxVy{x, y} => {
-                self.vreg[x as usize] = self.vreg[x as usize] & self.vreg[y as usize];
+                self.vreg[x as usize] &= self.vreg[y as usize];
+                self.logic_quirk();
             },
             Opcode::SUBVxVy{x, y} => {
                 let (val, borrowed) = self.vreg[x as usize].overflowing_sub(self.vreg[y as usize]);
                 self.vreg[x as usize] = val;
                 self.vreg[0xF] = if borrowed { 0 } else { 1 };
             },
-            Opcode::SHRVxVy{x, y: _} => {
-                self.vreg[0xF] = self.vreg[x as usize] & 0x1;
-                self.vreg[x as usize] = self.vreg[x as usize] >> 1;
+            Opcode::SHRVxVy{x, y} => {
+                let src = if self.quirks.shift { self.vreg[x as usize] } else { self.vreg[y as usize] };
+                self.vreg[x as usize] = src >> 1;
+                self.vreg[0xF] = src & 0x1;
             },
             Opcode::SUBNVxVy{x, y} => {
                 let (val, borrowed) = self.vreg[y as usize].overflowing_sub(self.vreg[x as usize]);
                 self.vreg[x as usize] = val;
                 self.vreg[0xF] = if borrowed { 0 } else { 1 };
             },
-            Opcode::SHLVxVy{x, y: _} => {
-                self.vreg[0xF] = self.vreg[x as usize] >> 7 & 0x1;
-                self.vreg[x as usize] = self.vreg[x as usize] << 1;
+            Opcode::SHLVxVy{x, y} => {
+                let src = if self.quirks.shift { self.vreg[x as usize] } else { self.vreg[y as usize] };
+                self.vreg[x as usize] = src << 1;
+                self.vreg[0xF] = src >> 7 & 0x1;
             },
             Opcode::SNEVxVy{x, y} => {
                 if self.vreg[x as usize] != self.vreg[y as usize] {
@@ -174,52 +309,65 @@ impl ProcState {
                 self.ireg = addr;
             },
             Opcode::JPV0Addr{addr} => {
-                self.pc = self.vreg[0x0] as u16 + addr;
+                let x = if self.quirks.jump { (addr >> 8) as usize } else { 0x0 };
+                self.pc = self.vreg[x] as u16 + addr;
             },
             Opcode::RNDVxByte{x, byte} => {
-                self.vreg[x as usize] = self.rand() & byte;
+                self.vreg[x as usize] = self.rand()? & byte;
             },
             Opcode::DRW{x, y, nibble} => {
-                let mut sprite_mask: [u64; SCREEN_HEIGHT] = [0x0; SCREEN_HEIGHT];
-                for i in 0 .. nibble {
-                    let sprite_line = self.mem[(self.ireg + i as u16) as usize];
-                    let xpos = self.vreg[x as usize];
-                    let ypos = self.vreg[y as usize];
-                    if (ypos as usize + i as usize) < SCREEN_HEIGHT {
-                        sprite_mask[(ypos as usize) + (i as usize)] = ((sprite_line as u64) << 56) >> (xpos as u64);
+                if self.quirks.vblank {
+                    if !self.vblank {
+                        self.pc = self.pc.wrapping_sub(2); // Wait for the display interrupt
+                        return Ok(());
                     }
+                    self.vblank = false;
                 }
 
+                // The starting position always wraps, the sprite itself is clipped unless the wrap quirk is set
+                let xpos = self.vreg[x as usize] as usize % SCREEN_WIDTH;
+                let ypos = self.vreg[y as usize] as usize % SCREEN_HEIGHT;
+
                 self.vreg[0xF] = 0;
-                let old_buffer = self.video_buffer;
-                for i in 0 .. SCREEN_HEIGHT {
-                    self.video_buffer[i] = sprite_mask[i] ^ self.video_buffer[i];
-                    if (old_buffer[i] & !self.video_buffer[i]) != 0 {
+                for i in 0 .. nibble as usize {
+                    let row = ypos + i;
+                    if row >= SCREEN_HEIGHT && !self.quirks.wrap {
+                        break;
+                    }
+
+                    let sprite_line = (self.mem[mem_addr(self.ireg as usize + i)] as u64) << 56;
+                    let sprite_mask = if self.quirks.wrap {
+                        sprite_line.rotate_right(xpos as u32)
+                    } else {
+                        sprite_line >> xpos
+                    };
+
+                    let row = row % SCREEN_HEIGHT;
+                    if self.video_buffer[row] & sprite_mask != 0 {
                         self.vreg[0xF] = 1;
                     }
+                    self.video_buffer[row] ^= sprite_mask;
                 }
             },
             Opcode::SKPVx{x} => {
-                let curr_key = self.io_queue.get();
-                match curr_key {
-                    None => (),
-                    Some(key) => if key == self.vreg[x as usize] { self.skip_next_instruction() }
+                let curr_key = self.io_queue.held();
+                if curr_key == Some(self.vreg[x as usize]) {
+                    self.skip_next_instruction();
                 }
             },
             Opcode::SKNPVx{x} => {
-                let curr_key = self.io_queue.get();
-                match curr_key {
-                    None => self.skip_next_instruction(),
-                    Some(key) => if key != self.vreg[x as usize] { self.skip_next_instruction() }
+                let curr_key = self.io_queue.held();
+                if curr_key != Some(self.vreg[x as usize]) {
+                    self.skip_next_instruction();
                 }
             },
             Opcode::LDVxDT{x} => {
                 self.vreg[x as usize] = self.delay_t;
             },
             Opcode::LDVxK{x} => {
-                let curr_key = self.io_queue.get();
+                let curr_key = self.io_queue.held();
                 match curr_key {
-                    None => self.pc = self.pc - 2, // Reset to give appearance of blocking
+                    None => self.pc = self.pc.wrapping_sub(2), // Reset to give appearance of blocking
                     Some(key) => self.vreg[x as usize] = key
                 }
             },
@@ -230,10 +378,11 @@ impl ProcState {
                 self.sound_t = self.vreg[x as usize];
             },
             Opcode::ADDIVx{x} => {
-                self.ireg = self.ireg + (self.vreg[x as usize] as u16);
+                self.ireg = self.ireg.wrapping_add(self.vreg[x as usize] as u16);
             },
             Opcode::LDFVx{x} => {
-                self.ireg = (self.vreg[x as usize] as u16) * 5;
+                // Only the low nibble selects a glyph, as on the COSMAC VIP
+                self.ireg = self.font_address.wrapping_add(((self.vreg[x as usize] & 0xF) as u16) * (SMALL_GLYPH_SIZE as u16));
             },
             Opcode::LDBVx{x} => {
                 let vx = self.vreg[x as usize];
@@ -241,40 +390,77 @@ impl ProcState {
                 let tens = (vx - (hundreds * 100)) / 10;
                 let ones = vx - (hundreds * 100) - (tens * 10);
 
-                self.mem[self.ireg as usize] = hundreds;
-                self.mem[(self.ireg as usize) + 1] = tens;
-                self.mem[(self.ireg as usize) + 2] = ones;
+                self.write_mem(self.ireg, hundreds);
+                self.write_mem(self.ireg.wrapping_add(1), tens);
+                self.write_mem(self.ireg.wrapping_add(2), ones);
             },
             Opcode::LDIVx{x} => {
                 for k in 0 ..= x {
-                    self.mem[(self.ireg as usize) + (k as usize)] = self.vreg[k as usize];
+                    self.write_mem(self.ireg.wrapping_add(k as u16), self.vreg[k as usize]);
                 }
+                self.memory_quirk(x);
             },
             Opcode::LDVxI{x} => {
                 for k in 0 ..= x {
-                    self.vreg[k as usize] = self.mem[(self.ireg as usize) + (k as usize)];
+                    self.vreg[k as usize] = self.mem[mem_addr(self.ireg as usize + k as usize)];
                 }
+                self.memory_quirk(x);
             },
-            Opcode::UNKNOWN{opcode: _} => panic!("unknown opcode {}", op),
+            Opcode::UNKNOWN{opcode: _} => return Err(Fault::UnknownOpcode(op)),
         }
+        Ok(())
     }
 
     fn skip_next_instruction(&mut self) {
-        self.pc = self.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.vreg[0xF] = 0;
+        }
     }
 
-    fn rand(&self) -> u8 {
-        // Generates a pseudo random number without needing an 3P create
-        // Credit: https://users.rust-lang.org/t/random-number-without-using-the-external-crate/17260/9
-        let nanos = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .subsec_nanos();
+    fn memory_quirk(&mut self, x: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        self.ireg = self.ireg.wrapping_add(if self.quirks.memory_increment_by_x { x as u16 } else { x as u16 + 1 });
+    }
 
-        return (nanos & 0xFF) as u8;
+    fn rand(&mut self) -> Result<u8, Fault> {
+        if let Some(state) = &mut self.rng {
+            // xorshift64*
+            *state ^= *state >> 12;
+            *state ^= *state << 25;
+            *state ^= *state >> 27;
+            return Ok((state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8);
+        }
+        if let Some(entropy) = &self.entropy {
+            return Ok(entropy.random_byte());
+        }
+        clock_rand()
     }
 }
 
+#[cfg(feature = "std")]
+fn clock_rand() -> Result<u8, Fault> {
+    // Generates a pseudo random number without needing an 3P create
+    // Credit: https://users.rust-lang.org/t/random-number-without-using-the-external-crate/17260/9
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+
+    Ok((nanos & 0xFF) as u8)
+}
+
+/// There is no clock to draw on without std.
+#[cfg(not(feature = "std"))]
+fn clock_rand() -> Result<u8, Fault> {
+    Err(Fault::NoRandomSource)
+}
+
 #[cfg(test)]
 mod test_cpu_basics {
     use std::cell::Cell;
@@ -357,8 +543,20 @@ mod test_cpu_execution {
     use std::cell::Cell;
     use std::rc::Rc;
 
-    use crate::cpu::{MAX_MEMORY_SIZE, ProcState};
+    use crate::cpu::{Entropy, Fault, MAX_MEMORY_SIZE, MAX_STACK_SIZE, ProcState};
     use crate::cpu::opcodes::Opcode;
+    use crate::SCREEN_HEIGHT;
+
+    #[test]
+    pub fn cls_clears_the_screen() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.video_buffer[0] = 0x8000_0000_0000_0001;
+        state.video_buffer[SCREEN_HEIGHT - 1] = u64::MAX;
+
+        state.execute_opcode(Opcode::CLS);
+
+        assert_eq!(state.video_buffer, [0x0; SCREEN_HEIGHT]);
+    }
 
     #[test]
     pub fn ret_decrements_stack_pointer() {
@@ -415,4 +613,183 @@ mod test_cpu_execution {
 
         assert_eq!(state.pop(), previous_pc);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn shift_quirk_selects_source_register() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.vreg[0x1] = 0x4;
+        state.vreg[0x2] = 0x81;
+
+        state.quirks.shift = false;
+        state.execute_opcode(Opcode::SHRVxVy { x: 0x1, y: 0x2 });
+        assert_eq!((state.vreg[0x1], state.vreg[0xF]), (0x40, 1));
+
+        state.quirks.shift = true;
+        state.execute_opcode(Opcode::SHRVxVy { x: 0x1, y: 0x2 });
+        assert_eq!((state.vreg[0x1], state.vreg[0xF]), (0x20, 0));
+    }
+
+    #[test]
+    pub fn logic_quirk_resets_vf() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.quirks.logic = true;
+        state.vreg[0xF] = 1;
+
+        state.execute_opcode(Opcode::ORVxVy { x: 0x0, y: 0x1 });
+
+        assert_eq!(state.vreg[0xF], 0);
+    }
+
+    #[test]
+    pub fn memory_quirks_control_i_after_store() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.ireg = 0x300;
+
+        state.execute_opcode(Opcode::LDIVx { x: 0x2 });
+        assert_eq!(state.ireg, 0x300);
+
+        state.quirks.memory_leave_i_unchanged = false;
+        state.execute_opcode(Opcode::LDIVx { x: 0x2 });
+        assert_eq!(state.ireg, 0x303);
+
+        state.quirks.memory_increment_by_x = true;
+        state.execute_opcode(Opcode::LDVxI { x: 0x2 });
+        assert_eq!(state.ireg, 0x305);
+    }
+
+    #[test]
+    pub fn jump_quirk_uses_vx_from_address() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.vreg[0x0] = 0x1;
+        state.vreg[0x3] = 0x2;
+
+        state.execute_opcode(Opcode::JPV0Addr { addr: 0x300 });
+        assert_eq!(state.pc, 0x301);
+
+        state.quirks.jump = true;
+        state.execute_opcode(Opcode::JPV0Addr { addr: 0x300 });
+        assert_eq!(state.pc, 0x302);
+    }
+
+    #[test]
+    pub fn vblank_quirk_delays_draw_until_tick() {
+        let mut mem = [0x0; MAX_MEMORY_SIZE];
+        mem[0x300] = 0x80;
+        let mut state = ProcState::new(mem, Rc::new(Cell::new(Option::None)));
+        state.quirks.vblank = true;
+        state.ireg = 0x300;
+
+        state.execute_opcode(Opcode::DRW { x: 0x0, y: 0x0, nibble: 1 });
+        assert_eq!(state.pc, 0x1FE);
+        assert_eq!(state.video_buffer[0], 0x0);
+
+        state.pc = 0x200;
+        state.clock_tick(1);
+        state.execute_opcode(Opcode::DRW { x: 0x0, y: 0x0, nibble: 1 });
+        assert_eq!(state.pc, 0x200);
+        assert_eq!(state.video_buffer[0], 0x1 << 63);
+    }
+
+    #[test]
+    pub fn wrap_quirk_wraps_sprites_around_edges() {
+        let mut mem = [0x0; MAX_MEMORY_SIZE];
+        mem[0x300] = 0xFF;
+        mem[0x301] = 0xFF;
+        let mut state = ProcState::new(mem, Rc::new(Cell::new(Option::None)));
+        state.ireg = 0x300;
+        state.vreg[0x0] = 60;
+        state.vreg[0x1] = 31;
+
+        state.execute_opcode(Opcode::DRW { x: 0x0, y: 0x1, nibble: 2 });
+        assert_eq!(state.video_buffer[31], 0xF);
+        assert_eq!(state.video_buffer[0], 0x0);
+
+        state.video_buffer = [0x0; crate::SCREEN_HEIGHT];
+        state.quirks.wrap = true;
+        state.execute_opcode(Opcode::DRW { x: 0x0, y: 0x1, nibble: 2 });
+        assert_eq!(state.video_buffer[31], 0xF000_0000_0000_000F);
+        assert_eq!(state.video_buffer[0], 0xF000_0000_0000_000F);
+    }
+
+    #[test]
+    pub fn font_glyph_is_offset_from_font_address() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.vreg[0x3] = 0x1A;
+
+        state.execute_opcode(Opcode::LDFVx { x: 0x3 });
+        assert_eq!(state.ireg, 0xA * 5);
+
+        state.font_address = 0x50;
+        state.execute_opcode(Opcode::LDFVx { x: 0x3 });
+        assert_eq!(state.ireg, 0x50 + 0xA * 5);
+    }
+
+    #[test]
+    pub fn seeded_rnd_repeats() {
+        let run = |seed| {
+            let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+            state.seed(seed);
+            (0 .. 16).map(|_| {
+                state.execute_opcode(Opcode::RNDVxByte { x: 0x0, byte: 0xFF });
+                state.vreg[0x0]
+            }).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(0), run(0));
+        assert_ne!(run(0), run(1));
+    }
+
+    #[test]
+    pub fn unseeded_rnd_draws_on_the_entropy_source() {
+        struct Fixed;
+        impl Entropy for Fixed {
+            fn random_byte(&self) -> u8 {
+                0xA5
+            }
+        }
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.entropy = Some(Rc::new(Fixed));
+
+        state.execute_opcode(Opcode::RNDVxByte { x: 0x0, byte: 0x0F });
+
+        assert_eq!(state.vreg[0x0], 0x05);
+    }
+
+    #[test]
+    pub fn faults_leave_state_unchanged() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+
+        assert_eq!(state.try_execute_opcode(Opcode::RET), Err(Fault::StackUnderflow));
+        assert_eq!((state.sp, state.pc), (0, 0x200));
+
+        for _ in 0 .. MAX_STACK_SIZE - 1 {
+            state.try_execute_opcode(Opcode::CALL { addr: 0x300 }).unwrap();
+        }
+        assert_eq!(state.try_execute_opcode(Opcode::CALL { addr: 0x400 }), Err(Fault::StackOverflow));
+        assert_eq!((state.sp, state.pc), (MAX_STACK_SIZE - 1, 0x300));
+
+        let unknown = Opcode::UNKNOWN { opcode: (0x5, 0x1, 0x2, 0x3) };
+        assert_eq!(state.try_execute_opcode(unknown), Err(Fault::UnknownOpcode(unknown)));
+    }
+
+    #[test]
+    pub fn memory_accesses_past_the_end_wrap_around() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(Option::None)));
+        state.ireg = 0xFFE;
+        state.vreg[0x0] = 0x1;
+        state.vreg[0x1] = 0x2;
+        state.vreg[0x2] = 0x3;
+
+        state.execute_opcode(Opcode::LDIVx { x: 0x2 });
+        assert_eq!((state.mem[0xFFF], state.mem[0x0]), (0x2, 0x3));
+
+        state.ireg = 0xFFFF;
+        state.execute_opcode(Opcode::ADDIVx { x: 0x0 });
+        state.execute_opcode(Opcode::DRW { x: 0x3, y: 0x3, nibble: 1 });
+        assert_eq!((state.ireg, state.video_buffer[0]), (0x0, 0x3 << 56));
+
+        state.pc = 0xFFFF;
+        state.fetch_and_decode_opcode();
+        assert_eq!(state.pc, 0x1);
+    }
+}
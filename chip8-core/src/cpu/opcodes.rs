@@ -1,4 +1,8 @@
-use std::fmt::{Display, Formatter, Result};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+use core::str::FromStr;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Opcode {
@@ -81,6 +85,182 @@ impl Display for Opcode {
     }
 }
 
+impl Opcode {
+    /// The instruction word, so that `get_opcode(op.encode()) == op`. Operands are truncated
+    /// to the bits their fields hold.
+    pub fn encode(&self) -> u16 {
+        let x = |x: u8| (x as u16 & 0xF) << 8;
+        let xy = |x: u8, y: u8| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let nnn = |addr: u16| addr & 0xFFF;
+
+        match *self {
+            Opcode::CLS => 0x00E0,
+            Opcode::RET => 0x00EE,
+            Opcode::JP{addr} => 0x1000 | nnn(addr),
+            Opcode::CALL{addr} => 0x2000 | nnn(addr),
+            Opcode::SEVxByte{x: vx, byte} => 0x3000 | x(vx) | byte as u16,
+            Opcode::SNEVxByte{x: vx, byte} => 0x4000 | x(vx) | byte as u16,
+            Opcode::SEVxVy{x, y} => 0x5000 | xy(x, y),
+            Opcode::LDVxByte{x: vx, byte} => 0x6000 | x(vx) | byte as u16,
+            Opcode::ADDVxByte{x: vx, byte} => 0x7000 | x(vx) | byte as u16,
+            Opcode::LDVxVy{x, y} => 0x8000 | xy(x, y),
+            Opcode::ORVxVy{x, y} => 0x8001 | xy(x, y),
+            Opcode::ANDVxVy{x, y} => 0x8002 | xy(x, y),
+            Opcode::XORVxVy{x, y} => 0x8003 | xy(x, y),
+            Opcode::ADDVxVy{x, y} => 0x8004 | xy(x, y),
+            Opcode::SUBVxVy{x, y} => 0x8005 | xy(x, y),
+            Opcode::SHRVxVy{x, y} => 0x8006 | xy(x, y),
+            Opcode::SUBNVxVy{x, y} => 0x8007 | xy(x, y),
+            Opcode::SHLVxVy{x, y} => 0x800E | xy(x, y),
+            Opcode::SNEVxVy{x, y} => 0x9000 | xy(x, y),
+            Opcode::LDIAddr{addr} => 0xA000 | nnn(addr),
+            Opcode::JPV0Addr{addr} => 0xB000 | nnn(addr),
+            Opcode::RNDVxByte{x: vx, byte} => 0xC000 | x(vx) | byte as u16,
+            Opcode::DRW{x, y, nibble} => 0xD000 | xy(x, y) | (nibble as u16 & 0xF),
+            Opcode::SKPVx{x: vx} => 0xE09E | x(vx),
+            Opcode::SKNPVx{x: vx} => 0xE0A1 | x(vx),
+            Opcode::LDVxDT{x: vx} => 0xF007 | x(vx),
+            Opcode::LDVxK{x: vx} => 0xF00A | x(vx),
+            Opcode::LDDTVx{x: vx} => 0xF015 | x(vx),
+            Opcode::LDSTVx{x: vx} => 0xF018 | x(vx),
+            Opcode::ADDIVx{x: vx} => 0xF01E | x(vx),
+            Opcode::LDFVx{x: vx} => 0xF029 | x(vx),
+            Opcode::LDBVx{x: vx} => 0xF033 | x(vx),
+            Opcode::LDIVx{x: vx} => 0xF055 | x(vx),
+            Opcode::LDVxI{x: vx} => 0xF065 | x(vx),
+            Opcode::UNKNOWN{opcode: (a, b, c, d)} => {
+                (a as u16 & 0xF) << 12 | (b as u16 & 0xF) << 8 | (c as u16 & 0xF) << 4 | (d as u16 & 0xF)
+            }
+        }
+    }
+}
+
+/// An operand as written in assembly.
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    Number(u32)
+}
+
+fn parse_operand(operand: &str) -> core::result::Result<Operand, String> {
+    let upper = operand.to_ascii_uppercase();
+    let number = match upper.as_str() {
+        "I" => return Ok(Operand::I),
+        "[I]" => return Ok(Operand::IndirectI),
+        "DT" => return Ok(Operand::DT),
+        "ST" => return Ok(Operand::ST),
+        "K" => return Ok(Operand::K),
+        "F" => return Ok(Operand::F),
+        "B" => return Ok(Operand::B),
+        reg if reg.starts_with('V') => {
+            return match u8::from_str_radix(&reg[1 ..], 16) {
+                Ok(x) if reg.len() == 2 => Ok(Operand::V(x)),
+                _ => Err(format!("invalid register: {}", operand))
+            };
+        },
+        hex if hex.starts_with("0X") => u32::from_str_radix(&hex[2 ..], 16),
+        decimal => decimal.parse::<u32>()
+    };
+    number.map(Operand::Number).map_err(|_| format!("invalid operand: {}", operand))
+}
+
+/// Checks a numeric operand fits in `bits`.
+fn in_range(what: &str, value: u32, bits: u32) -> core::result::Result<u32, String> {
+    let max = (1 << bits) - 1;
+    if value > max {
+        return Err(format!("{} {:#x} is out of range, the maximum is {:#x}", what, value, max));
+    }
+    Ok(value)
+}
+
+impl FromStr for Opcode {
+    type Err = String;
+
+    /// Parses the syntax `Display` produces, e.g. `LD V1, 0x2a` or `SHR V1 {, V2}`. Mnemonics,
+    /// registers and hex digits are case-insensitive and numbers may also be decimal.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (mnemonic, rest) = match s.find(char::is_whitespace) {
+            Some(idx) => (&s[.. idx], s[idx ..].trim()),
+            None => (s, "")
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        let rest = match mnemonic.as_str() {
+            "SHR" | "SHL" => rest.replace(['{', '}'], ""),
+            "UNKNOWN" => rest.strip_prefix('(')
+                .and_then(|rest| rest.strip_suffix(')'))
+                .ok_or_else(|| format!("expected (n, n, n, n) after UNKNOWN: {}", s))?
+                .to_string(),
+            _ => rest.to_string()
+        };
+        let operands = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|operand| parse_operand(operand.trim())).collect::<core::result::Result<Vec<_>, _>>()?
+        };
+
+        let addr = |value: u32| in_range("address", value, 12).map(|addr| addr as u16);
+        let byte = |value: u32| in_range("byte", value, 8).map(|byte| byte as u8);
+        let nibble = |value: u32| in_range("nibble", value, 4).map(|nibble| nibble as u8);
+
+        use Operand::*;
+        let opcode = match (mnemonic.as_str(), operands.as_slice()) {
+            ("CLS", []) => Opcode::CLS,
+            ("RET", []) => Opcode::RET,
+            ("JP", [Number(a)]) => Opcode::JP{ addr: addr(*a)? },
+            ("JP", [V(0x0), Number(a)]) => Opcode::JPV0Addr{ addr: addr(*a)? },
+            ("CALL", [Number(a)]) => Opcode::CALL{ addr: addr(*a)? },
+            ("SE", [V(x), Number(b)]) => Opcode::SEVxByte{ x: *x, byte: byte(*b)? },
+            ("SE", [V(x), V(y)]) => Opcode::SEVxVy{ x: *x, y: *y },
+            ("SNE", [V(x), Number(b)]) => Opcode::SNEVxByte{ x: *x, byte: byte(*b)? },
+            ("SNE", [V(x), V(y)]) => Opcode::SNEVxVy{ x: *x, y: *y },
+            ("LD", [V(x), Number(b)]) => Opcode::LDVxByte{ x: *x, byte: byte(*b)? },
+            ("LD", [V(x), V(y)]) => Opcode::LDVxVy{ x: *x, y: *y },
+            ("LD", [I, Number(a)]) => Opcode::LDIAddr{ addr: addr(*a)? },
+            ("LD", [V(x), DT]) => Opcode::LDVxDT{ x: *x },
+            ("LD", [V(x), K]) => Opcode::LDVxK{ x: *x },
+            ("LD", [DT, V(x)]) => Opcode::LDDTVx{ x: *x },
+            ("LD", [ST, V(x)]) => Opcode::LDSTVx{ x: *x },
+            ("LD", [F, V(x)]) => Opcode::LDFVx{ x: *x },
+            ("LD", [B, V(x)]) => Opcode::LDBVx{ x: *x },
+            ("LD", [IndirectI, V(x)]) => Opcode::LDIVx{ x: *x },
+            ("LD", [V(x), IndirectI]) => Opcode::LDVxI{ x: *x },
+            ("ADD", [V(x), Number(b)]) => Opcode::ADDVxByte{ x: *x, byte: byte(*b)? },
+            ("ADD", [V(x), V(y)]) => Opcode::ADDVxVy{ x: *x, y: *y },
+            ("ADD", [I, V(x)]) => Opcode::ADDIVx{ x: *x },
+            ("OR", [V(x), V(y)]) => Opcode::ORVxVy{ x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Opcode::ANDVxVy{ x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Opcode::XORVxVy{ x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Opcode::SUBVxVy{ x: *x, y: *y },
+            ("SHR", [V(x), V(y)]) => Opcode::SHRVxVy{ x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Opcode::SUBNVxVy{ x: *x, y: *y },
+            ("SHL", [V(x), V(y)]) => Opcode::SHLVxVy{ x: *x, y: *y },
+            ("RND", [V(x), Number(b)]) => Opcode::RNDVxByte{ x: *x, byte: byte(*b)? },
+            ("DRW", [V(x), V(y), Number(n)]) => Opcode::DRW{ x: *x, y: *y, nibble: nibble(*n)? },
+            ("SKP", [V(x)]) => Opcode::SKPVx{ x: *x },
+            ("SKNP", [V(x)]) => Opcode::SKNPVx{ x: *x },
+            ("UNKNOWN", [Number(a), Number(b), Number(c), Number(d)]) => {
+                Opcode::UNKNOWN{ opcode: (nibble(*a)?, nibble(*b)?, nibble(*c)?, nibble(*d)?) }
+            },
+            ("CLS", _) | ("RET", _) | ("JP", _) | ("CALL", _) | ("SE", _) | ("SNE", _) | ("LD", _) |
+            ("ADD", _) | ("OR", _) | ("AND", _) | ("XOR", _) | ("SUB", _) | ("SHR", _) | ("SUBN", _) |
+            ("SHL", _) | ("RND", _) | ("DRW", _) | ("SKP", _) | ("SKNP", _) | ("UNKNOWN", _) => {
+                return Err(format!("invalid operands for {}: {}", mnemonic, s));
+            },
+            _ => return Err(format!("unknown instruction: {}", s))
+        };
+        Ok(opcode)
+    }
+}
+
 pub fn get_opcode(op: u16) -> Opcode {
     match split_opcode(op) {
         (0x0, 0x0, 0xE, 0x0) => Opcode::CLS,
@@ -182,4 +362,38 @@ mod tests {
     pub fn op_n_pulls_correct_value() {
         assert_eq!((0xF), op_n(0xCDEF));
     }
+
+    #[test]
+    pub fn every_instruction_word_encodes_back_to_itself() {
+        for word in 0 ..= 0xFFFF {
+            let opcode = get_opcode(word);
+            assert_eq!(opcode.encode(), word, "{}", opcode);
+            assert_eq!(get_opcode(opcode.encode()), opcode);
+        }
+    }
+
+    #[test]
+    pub fn every_instruction_parses_from_its_display() {
+        for word in 0 ..= 0xFFFF {
+            let opcode = get_opcode(word);
+            assert_eq!(opcode.to_string().parse::<Opcode>(), Ok(opcode), "{:04X}", word);
+        }
+    }
+
+    #[test]
+    pub fn parsing_accepts_any_case_and_decimal() {
+        assert_eq!("ld va, 42".parse(), Ok(Opcode::LDVxByte { x: 0xA, byte: 42 }));
+        assert_eq!("  drw V0,V1,0xF ".parse(), Ok(Opcode::DRW { x: 0x0, y: 0x1, nibble: 0xF }));
+        assert_eq!("ld [i], vF".parse(), Ok(Opcode::LDIVx { x: 0xF }));
+    }
+
+    #[test]
+    pub fn parse_errors_describe_the_problem() {
+        assert_eq!("JP 0x1000".parse::<Opcode>(), Err("address 0x1000 is out of range, the maximum is 0xfff".to_string()));
+        assert_eq!("LD V1, 256".parse::<Opcode>(), Err("byte 0x100 is out of range, the maximum is 0xff".to_string()));
+        assert_eq!("LD VG, 1".parse::<Opcode>(), Err("invalid register: VG".to_string()));
+        assert_eq!("JP V1, 0x200".parse::<Opcode>(), Err("invalid operands for JP: JP V1, 0x200".to_string()));
+        assert_eq!("MOV V1, V2".parse::<Opcode>(), Err("unknown instruction: MOV V1, V2".to_string()));
+        assert_eq!("LD V1, q".parse::<Opcode>(), Err("invalid operand: q".to_string()));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::cart::Cartridge;
+use crate::color::Rgb;
+use crate::quirks::{Platform, Quirks};
+
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+
+/// Everything known about a single ROM image.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Quirks,
+    /// Instructions per 60Hz frame.
+    pub tickrate: Option<u32>,
+    /// Background first, then foreground.
+    pub colors: Vec<Rgb>,
+    /// Game actions (`up`, `player2Down`, ...) mapped to keypad keys.
+    pub keys: BTreeMap<String, u8>
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Invalid(String)
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(e) => write!(f, "failed to read ROM database: {}", e),
+            DbError::Parse(e) => write!(f, "malformed ROM database: {}", e),
+            DbError::Invalid(msg) => write!(f, "invalid ROM database: {}", msg)
+        }
+    }
+}
+
+impl Error for DbError {}
+
+impl From<io::Error> for DbError {
+    fn from(e: io::Error) -> Self {
+        DbError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Parse(e)
+    }
+}
+
+/// ROM metadata keyed by the SHA-1 of the ROM image.
+///
+/// The JSON layout is the `programs.json` file of the community chip-8-database: an array of
+/// programs, each holding a `roms` object keyed by SHA-1.
+#[derive(Default)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>
+}
+
+impl RomDatabase {
+    /// The database shipped with chip8-core.
+    pub fn bundled() -> RomDatabase {
+        RomDatabase::from_json(BUNDLED_PROGRAMS).expect("bundled ROM database is valid")
+    }
+
+    pub fn from_json(json: &str) -> Result<RomDatabase, DbError> {
+        let programs: Vec<Program> = serde_json::from_str(json)?;
+        let mut roms = HashMap::new();
+
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let info = rom.resolve(&program.title, &program.authors)?;
+                roms.insert(hash.to_ascii_lowercase(), info);
+            }
+        }
+
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn load(path: &Path) -> Result<RomDatabase, DbError> {
+        RomDatabase::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Adds the entries of `other`, replacing any existing entries for the same ROM.
+    pub fn extend(&mut self, other: RomDatabase) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+
+    pub fn lookup(&self, cart: &Cartridge) -> Option<&RomInfo> {
+        self.get(&cart.sha1())
+    }
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: BTreeMap<String, RomEntry>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: BTreeMap<String, QuirkOverrides>,
+    tickrate: Option<u32>,
+    colors: Option<ColorEntry>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>
+}
+
+#[derive(Deserialize)]
+struct ColorEntry {
+    #[serde(default)]
+    pixels: Vec<String>
+}
+
+impl RomEntry {
+    fn resolve(self, title: &str, authors: &[String]) -> Result<RomInfo, DbError> {
+        // The database lists platforms in order of preference; pick the first one we emulate
+        let platform = self.platforms.iter().find_map(|id| id.parse::<Platform>().ok());
+
+        let mut quirks = platform.map(|p| p.quirks()).unwrap_or_default();
+        if let Some(overrides) = platform.and_then(|p| self.quirky_platforms.get(p.id())) {
+            overrides.apply(&mut quirks);
+        }
+
+        let colors = match self.colors {
+            Some(colors) => colors.pixels.iter()
+                .map(|c| c.parse::<Rgb>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DbError::Invalid)?,
+            None => Vec::new()
+        };
+
+        if let Some((action, key)) = self.keys.iter().find(|(_, key)| **key > 0xF) {
+            return Err(DbError::Invalid(format!("{}: key {} for {} is not on the keypad", title, key, action)));
+        }
+
+        Ok(RomInfo {
+            title: title.to_string(),
+            authors: authors.to_vec(),
+            platform,
+            quirks,
+            tickrate: self.tickrate.or_else(|| platform.map(|p| p.tickrate())),
+            colors,
+            keys: self.keys
+        })
+    }
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.shift, &mut quirks.shift),
+            (self.memory_increment_by_x, &mut quirks.memory_increment_by_x),
+            (self.memory_leave_i_unchanged, &mut quirks.memory_leave_i_unchanged),
+            (self.wrap, &mut quirks.wrap),
+            (self.jump, &mut quirks.jump),
+            (self.vblank, &mut quirks.vblank),
+            (self.logic, &mut quirks.logic)
+        ];
+
+        for (value, quirk) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Test Game",
+            "authors": ["Someone"],
+            "roms": {
+                "ABCDEF": {
+                    "file": "test.ch8",
+                    "platforms": ["megachip8", "originalChip8"],
+                    "quirkyPlatforms": { "originalChip8": { "shift": true } },
+                    "colors": { "pixels": ["#000000", "#ff0000"] },
+                    "keys": { "up": 5 }
+                }
+            }
+        }
+    ]"##;
+
+    #[test]
+    pub fn bundled_database_is_valid() {
+        RomDatabase::bundled();
+    }
+
+    #[test]
+    pub fn lookup_is_case_insensitive() {
+        let db = RomDatabase::from_json(PROGRAMS).unwrap();
+
+        assert_eq!(db.get("abcdef").unwrap().title, "Test Game");
+    }
+
+    #[test]
+    pub fn first_supported_platform_is_chosen_and_quirks_applied() {
+        let db = RomDatabase::from_json(PROGRAMS).unwrap();
+        let info = db.get("abcdef").unwrap();
+
+        let mut expected = Platform::OriginalChip8.quirks();
+        expected.shift = true;
+
+        assert_eq!(info.platform, Some(Platform::OriginalChip8));
+        assert_eq!(info.quirks, expected);
+        assert_eq!(info.tickrate, Some(Platform::OriginalChip8.tickrate()));
+        assert_eq!(info.colors, vec![Rgb::BLACK, Rgb { r: 255, g: 0, b: 0 }]);
+        assert_eq!(info.keys.get("up"), Some(&5));
+    }
+
+    #[test]
+    pub fn extend_replaces_existing_entries() {
+        let mut db = RomDatabase::from_json(PROGRAMS).unwrap();
+        let user = RomDatabase::from_json(r#"[{ "title": "Renamed", "roms": { "abcdef": {} } }]"#).unwrap();
+
+        db.extend(user);
+
+        assert_eq!(db.get("abcdef").unwrap().title, "Renamed");
+    }
+
+    #[test]
+    pub fn keys_outside_keypad_are_rejected() {
+        let json = r#"[{ "title": "Bad", "roms": { "aa": { "keys": { "up": 16 } } } }]"#;
+
+        assert!(matches!(RomDatabase::from_json(json), Err(DbError::Invalid(_))));
+    }
+}
@@ -0,0 +1,245 @@
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Pixels dimmer than this are treated as off by frontends that can only show on and off.
+pub const LIT_THRESHOLD: u8 = 128;
+
+/// What the screen looks like after filtering, as one brightness per pixel.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Row by row, 0 for off up to 255 for fully on.
+    pub pixels: Vec<u8>
+}
+
+impl Frame {
+    /// An unfiltered frame straight from a video buffer.
+    pub fn from_rows(video_buffer: &[u64]) -> Frame {
+        let pixels = video_buffer.iter()
+            .flat_map(|line| (0 .. SCREEN_WIDTH).map(move |x| lit(*line, x)))
+            .map(|on| if on { 0xFF } else { 0x0 })
+            .collect();
+
+        Frame { width: SCREEN_WIDTH, height: video_buffer.len(), pixels }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) >= LIT_THRESHOLD
+    }
+}
+
+fn lit(line: u64, x: usize) -> bool {
+    (line >> (SCREEN_WIDTH - 1 - x)) & 0x1 == 0x1
+}
+
+/// Ways of hiding the flicker caused by sprites being erased and redrawn with XOR. They can be
+/// combined, and all of them are off by default.
+///
+/// Written as a comma separated list, e.g. `blend=3,phosphor=0.7,vblank`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Filters {
+    /// A pixel is on if it was on in any of the last this many frames.
+    pub blend_frames: usize,
+    /// How much of its brightness a pixel keeps each frame after it is turned off, between 0
+    /// (none) and 1.
+    pub persistence: f32,
+    /// Show the screen as it was when the program last waited for the next frame, by reading the
+    /// delay timer or waiting for a key, rather than as it is at the end of the frame.
+    pub vblank_only: bool
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters { blend_frames: 1, persistence: 0.0, vblank_only: false }
+    }
+}
+
+impl FromStr for Filters {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filters = Filters::default();
+
+        for filter in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (name, value) = match filter.find('=') {
+                Some(idx) => (&filter[.. idx], Some(&filter[idx + 1 ..])),
+                None => (filter, None)
+            };
+            let invalid = || format!("invalid filter: {}", filter);
+
+            match (name, value) {
+                ("blend", Some(frames)) => {
+                    filters.blend_frames = frames.parse().map_err(|_| invalid())?;
+                    if filters.blend_frames == 0 {
+                        return Err(invalid());
+                    }
+                },
+                ("phosphor", Some(persistence)) => {
+                    filters.persistence = persistence.parse().map_err(|_| invalid())?;
+                    if !(0.0 ..= 1.0).contains(&filters.persistence) {
+                        return Err(invalid());
+                    }
+                },
+                ("vblank", None) => filters.vblank_only = true,
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(filters)
+    }
+}
+
+impl fmt::Display for Filters {
+    /// The filters that are on, in the form `FromStr` reads.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut filters = Vec::new();
+        if self.blend_frames > 1 {
+            filters.push(format!("blend={}", self.blend_frames));
+        }
+        if self.persistence > 0.0 {
+            filters.push(format!("phosphor={}", self.persistence));
+        }
+        if self.vblank_only {
+            filters.push("vblank".into());
+        }
+        write!(f, "{}", filters.join(","))
+    }
+}
+
+/// Applies `Filters` to successive frames of the video buffer.
+pub struct Display {
+    pub filters: Filters,
+    history: VecDeque<[u64; SCREEN_HEIGHT]>,
+    brightness: Vec<f32>,
+    frame: Frame
+}
+
+impl Display {
+    pub fn new(filters: Filters) -> Display {
+        Display {
+            filters,
+            history: VecDeque::new(),
+            brightness: vec![0.0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: Frame::from_rows(&[0x0; SCREEN_HEIGHT])
+        }
+    }
+
+    /// Feeds the next frame's video buffer through the filters.
+    pub fn update(&mut self, video_buffer: &[u64; SCREEN_HEIGHT]) {
+        self.history.push_back(*video_buffer);
+        while self.history.len() > self.filters.blend_frames.max(1) {
+            self.history.pop_front();
+        }
+
+        let mut blended = [0x0; SCREEN_HEIGHT];
+        for frame in &self.history {
+            for (row, line) in blended.iter_mut().zip(frame.iter()) {
+                *row |= line;
+            }
+        }
+
+        let persistence = self.filters.persistence;
+        for (y, line) in blended.iter().enumerate() {
+            for x in 0 .. SCREEN_WIDTH {
+                let idx = y * SCREEN_WIDTH + x;
+                let target: f32 = if lit(*line, x) { 1.0 } else { 0.0 };
+                let mut brightness = target.max(self.brightness[idx] * persistence);
+                // Let decay finish rather than leave pixels at an invisible 0.1%
+                if brightness < 0.5 / 255.0 {
+                    brightness = 0.0;
+                }
+
+                self.brightness[idx] = brightness;
+                // Rounds half up without `f32::round`, which needs std
+                self.frame.pixels[idx] = (brightness * 255.0 + 0.5) as u8;
+            }
+        }
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(pixels: &[(usize, usize)]) -> [u64; SCREEN_HEIGHT] {
+        let mut buffer = [0x0; SCREEN_HEIGHT];
+        for (x, y) in pixels {
+            buffer[*y] |= 0x1 << (SCREEN_WIDTH - 1 - x);
+        }
+        buffer
+    }
+
+    #[test]
+    pub fn no_filters_shows_the_buffer_as_is() {
+        let mut display = Display::new(Filters::default());
+
+        display.update(&screen(&[(0, 0), (63, 31)]));
+        display.update(&screen(&[(5, 5)]));
+
+        assert_eq!(display.frame(), &Frame::from_rows(&screen(&[(5, 5)])));
+    }
+
+    #[test]
+    pub fn blend_ors_the_last_frames() {
+        let mut display = Display::new("blend=2".parse().unwrap());
+
+        display.update(&screen(&[(1, 1)]));
+        display.update(&screen(&[(2, 2)]));
+        assert!(display.frame().is_lit(1, 1) && display.frame().is_lit(2, 2));
+
+        display.update(&screen(&[]));
+        assert!(!display.frame().is_lit(1, 1) && display.frame().is_lit(2, 2));
+    }
+
+    #[test]
+    pub fn phosphor_decays_exponentially() {
+        let mut display = Display::new("phosphor=0.5".parse().unwrap());
+
+        display.update(&screen(&[(3, 4)]));
+        display.update(&screen(&[]));
+        assert_eq!(display.frame().pixel(3, 4), 128);
+
+        display.update(&screen(&[]));
+        assert_eq!(display.frame().pixel(3, 4), 64);
+
+        for _ in 0 .. 10 {
+            display.update(&screen(&[]));
+        }
+        assert_eq!(display.frame().pixel(3, 4), 0);
+    }
+
+    #[test]
+    pub fn filters_parse_from_a_list() {
+        let filters: Filters = "blend=3, phosphor=0.7,vblank".parse().unwrap();
+
+        assert_eq!(filters, Filters { blend_frames: 3, persistence: 0.7, vblank_only: true });
+        assert!("blend=0".parse::<Filters>().is_err());
+        assert!("phosphor=2".parse::<Filters>().is_err());
+        assert!("sharpen".parse::<Filters>().is_err());
+    }
+
+    #[test]
+    pub fn filters_round_trip_through_their_list() {
+        for list in ["", "blend=3", "phosphor=0.7,vblank", "blend=2,phosphor=0.25,vblank"] {
+            let filters: Filters = list.parse().unwrap();
+
+            assert_eq!(filters.to_string(), list);
+        }
+    }
+}
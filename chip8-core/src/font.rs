@@ -1,3 +1,19 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+pub const SMALL_GLYPH_SIZE: usize = 5;
+pub const BIG_GLYPH_SIZE: usize = 10;
+
 pub const FONT_SPRITES: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -15,4 +31,298 @@ pub const FONT_SPRITES: [u8; 80] = [
     0xE0, 0x90, 0x90, 0x90, 0xE0, // D
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
\ No newline at end of file
+];
+
+const VIP_SPRITES: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+const DREAM_6800_SPRITES: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+const ETI_660_SPRITES: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+const FISH_N_CHIPS_SPRITES: [u8; 80] = [
+    0x60, 0xA0, 0xA0, 0xA0, 0xC0, // 0
+    0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+    0xC0, 0x20, 0x40, 0x80, 0xE0, // 2
+    0xC0, 0x20, 0x40, 0x20, 0xC0, // 3
+    0x20, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xC0, 0x20, 0xC0, // 5
+    0x40, 0x80, 0xC0, 0xA0, 0x40, // 6
+    0xE0, 0x20, 0x60, 0x40, 0x40, // 7
+    0x40, 0xA0, 0x40, 0xA0, 0x40, // 8
+    0x40, 0xA0, 0x60, 0x20, 0x40, // 9
+    0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+    0x60, 0x80, 0x80, 0x80, 0x60, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+// SUPER-CHIP 1.1 only shipped large glyphs for the decimal digits
+const SCHIP_BIG_SPRITES: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C  // 9
+];
+
+const OCTO_BIG_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+/// The 4x5 hex digit glyphs shipped with historical interpreters.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SmallFont {
+    CosmacVip,
+    Dream6800,
+    Eti660,
+    SuperChip,
+    FishNChips
+}
+
+impl SmallFont {
+    pub const ALL: [SmallFont; 5] = [
+        SmallFont::CosmacVip,
+        SmallFont::Dream6800,
+        SmallFont::Eti660,
+        SmallFont::SuperChip,
+        SmallFont::FishNChips
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            SmallFont::CosmacVip => "vip",
+            SmallFont::Dream6800 => "dream6800",
+            SmallFont::Eti660 => "eti660",
+            SmallFont::SuperChip => "schip",
+            SmallFont::FishNChips => "fish"
+        }
+    }
+
+    pub fn sprites(&self) -> &'static [u8; 80] {
+        match self {
+            SmallFont::CosmacVip => &VIP_SPRITES,
+            SmallFont::Dream6800 => &DREAM_6800_SPRITES,
+            SmallFont::Eti660 => &ETI_660_SPRITES,
+            SmallFont::SuperChip => &FONT_SPRITES,
+            SmallFont::FishNChips => &FISH_N_CHIPS_SPRITES
+        }
+    }
+}
+
+impl FromStr for SmallFont {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SmallFont::ALL.iter()
+            .find(|f| f.id().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown font: {}", s))
+    }
+}
+
+/// The 8x10 glyphs used by SUPER-CHIP and later interpreters.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BigFont {
+    SuperChip,
+    Octo
+}
+
+impl BigFont {
+    pub fn sprites(&self) -> &'static [u8] {
+        match self {
+            BigFont::SuperChip => &SCHIP_BIG_SPRITES,
+            BigFont::Octo => &OCTO_BIG_SPRITES
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    /// Reading the font failed, e.g. with an `io::Error`.
+    Io(Box<dyn Error + Send + Sync>),
+    WrongSize { size: usize }
+}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::Io(e) => write!(f, "failed to read font: {}", e),
+            FontError::WrongSize{size} => {
+                write!(f, "font is {} bytes, expected 80, 180 or 240 (small glyphs, then optional big glyphs)", size)
+            }
+        }
+    }
+}
+
+impl Error for FontError {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for FontError {
+    fn from(e: io::Error) -> Self {
+        FontError::Io(Box::new(e))
+    }
+}
+
+/// The glyphs loaded into interpreter memory: 16 small glyphs, optionally followed by big ones.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Font {
+    pub small: [u8; 80],
+    pub big: Vec<u8>
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::from(SmallFont::SuperChip)
+    }
+}
+
+impl From<SmallFont> for Font {
+    fn from(small: SmallFont) -> Self {
+        Font { small: *small.sprites(), big: Vec::new() }
+    }
+}
+
+impl Font {
+    pub fn with_big(mut self, big: BigFont) -> Self {
+        self.big = big.sprites().to_vec();
+        self
+    }
+
+    /// Reads a raw font: 80 bytes of small glyphs, optionally followed by 100 bytes (digits only)
+    /// or 160 bytes of big glyphs.
+    #[cfg(feature = "std")]
+    pub fn load(reader: &mut dyn Read) -> Result<Font, FontError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        match bytes.len() {
+            80 | 180 | 240 => {
+                let mut small = [0x0; 80];
+                small.copy_from_slice(&bytes[.. 80]);
+                Ok(Font { small, big: bytes[80 ..].to_vec() })
+            },
+            size => Err(FontError::WrongSize { size })
+        }
+    }
+
+    /// The bytes to place in memory at the font base address.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = self.small.to_vec();
+        bytes.extend_from_slice(&self.big);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn small_fonts_round_trip_through_their_ids() {
+        for font in SmallFont::ALL.iter() {
+            assert_eq!(font.id().parse::<SmallFont>(), Ok(*font));
+        }
+    }
+
+    #[test]
+    pub fn default_font_is_the_original_sprite_table() {
+        assert_eq!(Font::default().bytes(), FONT_SPRITES.to_vec());
+    }
+
+    #[test]
+    pub fn big_glyphs_follow_small_glyphs() {
+        let bytes = Font::from(SmallFont::CosmacVip).with_big(BigFont::Octo).bytes();
+
+        assert_eq!(bytes.len(), 80 + 16 * BIG_GLYPH_SIZE);
+        assert_eq!(&bytes[80 ..], BigFont::Octo.sprites());
+    }
+
+    #[test]
+    pub fn load_accepts_small_and_big_glyphs() {
+        let mut bytes = VIP_SPRITES.to_vec();
+        bytes.extend_from_slice(&SCHIP_BIG_SPRITES);
+
+        let font = Font::load(&mut &bytes[..]).unwrap();
+
+        assert_eq!(font, Font::from(SmallFont::CosmacVip).with_big(BigFont::SuperChip));
+    }
+
+    #[test]
+    pub fn load_rejects_unexpected_sizes() {
+        assert!(matches!(Font::load(&mut &[0x0; 81][..]), Err(FontError::WrongSize { size: 81 })));
+        assert!(matches!(Font::load(&mut &[0x0; 300][..]), Err(FontError::WrongSize { size: 300 })));
+    }
+}
@@ -0,0 +1,580 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::cpu::opcodes::Opcode;
+use crate::cpu::ProcState;
+use crate::display::{Display, Filters, Frame};
+use crate::machine::Machine;
+
+/// The length of one 60Hz frame, the rate of the delay and sound timers.
+pub const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+/// Emulation speeds selectable with `Command::SpeedUp` and `Command::SlowDown`.
+pub const SPEEDS: [f32; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 3;
+
+/// Something that shows the screen.
+pub trait Video {
+    /// Shows a frame, after any display filters have been applied.
+    fn present(&mut self, frame: &Frame) -> Result<(), String>;
+
+    /// Switches between windowed and fullscreen, for frontends that have windows.
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Shows the emulator's speed and whether it is paused, e.g. in the window title.
+    fn set_status(&mut self, _status: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Something that reads the host keyboard, gamepad or terminal.
+pub trait Keypad {
+    /// Handles any pending host input, returning a command for the emulation loop if the user
+    /// asked for one. Called once per frame and then again for as long as it returns commands.
+    fn poll(&mut self) -> Option<Command>;
+
+    /// The keypad key currently held down.
+    fn held(&self) -> Option<u8>;
+
+    /// A screen to show instead of the emulator's while the keypad needs the user's attention,
+    /// such as when remapping keys. Emulation is paused while there is one.
+    fn overlay(&self) -> Option<Frame> {
+        None
+    }
+}
+
+/// Something that plays the buzzer.
+pub trait Audio {
+    /// Called once per frame with whether the sound timer is running.
+    fn set_tone(&mut self, on: bool);
+}
+
+/// Something that paces the emulation loop.
+pub trait Timer {
+    /// Called once per frame after the screen has been presented, returns when the next frame
+    /// should start.
+    fn wait_for_frame(&mut self);
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Command {
+    Quit,
+    ToggleFullscreen,
+    TogglePause,
+    /// Runs a single frame and pauses.
+    FrameAdvance,
+    SpeedUp,
+    SlowDown,
+    NormalSpeed,
+    /// Restarts the current ROM.
+    Reset,
+    /// Stops running this ROM so the frontend can start the named one.
+    Load(String)
+}
+
+/// Why `Emulator::run` returned.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Exit {
+    Quit,
+    Load(String)
+}
+
+/// How the screen is fitted into a window of a different size.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Scaling {
+    /// The largest whole multiple of the screen size that fits, so every pixel is the same size.
+    Integer,
+    /// As large as fits while keeping the screen's aspect ratio.
+    Aspect,
+    /// Fills the window, ignoring the aspect ratio.
+    Stretch
+}
+
+impl Scaling {
+    /// Where a `screen` sized image goes in a `window` sized area, as x, y, width and height.
+    /// The image is centred and anything around it is letterboxing.
+    pub fn viewport(&self, window: (u32, u32), screen: (u32, u32)) -> (i32, i32, u32, u32) {
+        let (window_width, window_height) = window;
+        let (screen_width, screen_height) = screen;
+
+        let (width, height) = match self {
+            Scaling::Integer => {
+                let scale = (window_width / screen_width).min(window_height / screen_height).max(1);
+                (screen_width * scale, screen_height * scale)
+            },
+            Scaling::Aspect => {
+                // Compare window_width / window_height with screen_width / screen_height
+                if window_width as u64 * screen_height as u64 > window_height as u64 * screen_width as u64 {
+                    ((window_height as u64 * screen_width as u64 / screen_height as u64) as u32, window_height)
+                } else {
+                    (window_width, (window_width as u64 * screen_height as u64 / screen_width as u64) as u32)
+                }
+            },
+            Scaling::Stretch => (window_width, window_height)
+        };
+
+        let x = (window_width as i32 - width as i32) / 2;
+        let y = (window_height as i32 - height as i32) / 2;
+        (x, y, width, height)
+    }
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scaling::Integer => "integer",
+            Scaling::Aspect => "aspect",
+            Scaling::Stretch => "stretch"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "integer" => Ok(Scaling::Integer),
+            "aspect" => Ok(Scaling::Aspect),
+            "stretch" => Ok(Scaling::Stretch),
+            _ => Err(format!("unknown scaling: {}", s))
+        }
+    }
+}
+
+/// Where `Emulator::trace` writes a line for every instruction executed. With std, any
+/// `io::Write` is one; `FmtTrace` makes one of any `fmt::Write`.
+pub trait TraceOut {
+    fn write_line(&mut self, line: fmt::Arguments<'_>) -> Result<(), String>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> TraceOut for W {
+    fn write_line(&mut self, line: fmt::Arguments<'_>) -> Result<(), String> {
+        writeln!(self, "{}", line).map_err(|e| e.to_string())
+    }
+}
+
+/// Traces into a `fmt::Write`, such as a `String`.
+pub struct FmtTrace<W: fmt::Write>(pub W);
+
+impl<W: fmt::Write> TraceOut for FmtTrace<W> {
+    fn write_line(&mut self, line: fmt::Arguments<'_>) -> Result<(), String> {
+        writeln!(self.0, "{}", line).map_err(|e| e.to_string())
+    }
+}
+
+/// Sleeps out the rest of each 60Hz frame.
+#[cfg(feature = "std")]
+pub struct FrameTimer {
+    frame_start: Instant
+}
+
+#[cfg(feature = "std")]
+impl FrameTimer {
+    pub fn new() -> FrameTimer {
+        FrameTimer { frame_start: Instant::now() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for FrameTimer {
+    fn default() -> Self {
+        FrameTimer::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Timer for FrameTimer {
+    fn wait_for_frame(&mut self) {
+        thread::sleep(FRAME_DURATION.saturating_sub(self.frame_start.elapsed()));
+        self.frame_start = Instant::now();
+    }
+}
+
+/// Runs frames back to back, for headless runs and tests.
+pub struct Unthrottled;
+
+impl Timer for Unthrottled {
+    fn wait_for_frame(&mut self) {}
+}
+
+/// For frontends without sound.
+pub struct Silent;
+
+impl Audio for Silent {
+    fn set_tone(&mut self, _on: bool) {}
+}
+
+/// The emulation loop shared by every frontend.
+pub struct Emulator {
+    pub machine: Machine,
+    /// Instructions executed per frame, unless the machine has VIP timing.
+    pub tickrate: u32,
+    pub display: Display,
+    pub paused: bool,
+    /// Frames emulated since the ROM was started.
+    pub frames: u64,
+    /// Index into `SPEEDS`.
+    speed: usize,
+    /// Frames owed at the current speed, in fractions of a frame.
+    frame_budget: f32,
+    trace: Option<Box<dyn TraceOut>>
+}
+
+impl Emulator {
+    pub fn new(machine: Machine, tickrate: u32) -> Emulator {
+        Emulator {
+            machine,
+            tickrate,
+            display: Display::new(Filters::default()),
+            paused: false,
+            frames: 0,
+            speed: NORMAL_SPEED,
+            frame_budget: 0.0,
+            trace: None
+        }
+    }
+
+    pub fn filters(mut self, filters: Filters) -> Self {
+        self.display = Display::new(filters);
+        self
+    }
+
+    /// Writes the processor state and instruction to `out` for every instruction executed.
+    pub fn trace(mut self, out: Box<dyn TraceOut>) -> Self {
+        self.trace = Some(out);
+        self
+    }
+
+    /// Runs one 60Hz frame: `tickrate` instructions followed by a tick of the timers, or under
+    /// VIP timing as many instructions as fit before the display interrupt, then updates the
+    /// display. `key` is held down as in `Machine::set_key`.
+    pub fn frame(&mut self, key: Option<u8>) -> Result<(), String> {
+        self.machine.set_key(key);
+        let mut waiting_screen = None;
+
+        let trace = &mut self.trace;
+        let mut trace_result = Ok(());
+        let on_step = |state: &ProcState, opcode| {
+            if let (Some(out), Ok(())) = (trace.as_mut(), &trace_result) {
+                trace_result = out.write_line(format_args!("ProcState: {}, Instruction: {}, KeyPress: {:?}", state, &opcode, key));
+            }
+
+            // Programs poll the delay timer or wait for a key once they have finished drawing
+            if let Opcode::LDVxDT{..} | Opcode::LDVxK{..} = opcode {
+                waiting_screen = Some(state.video_buffer);
+            }
+        };
+        let result = if self.machine.vip_timing() {
+            self.machine.run_frame(on_step)
+        } else {
+            self.machine.run(self.tickrate, on_step).map(|()| self.machine.state.clock_tick(1))
+        };
+        trace_result?;
+        result.map_err(|fault| format!("{} ({})", fault, &self.machine.state))?;
+        self.frames += 1;
+
+        let screen = match waiting_screen {
+            Some(screen) if self.display.filters.vblank_only => screen,
+            _ => self.machine.state.video_buffer
+        };
+        self.display.update(&screen);
+        Ok(())
+    }
+
+    /// Emulated frames per real frame.
+    pub fn speed(&self) -> f32 {
+        SPEEDS[self.speed]
+    }
+
+    /// The speed as a percentage, and whether emulation is paused.
+    pub fn status(&self) -> String {
+        let speed = format!("{}%", (self.speed() * 100.0) as u32);
+        if self.paused { format!("{} - paused", speed) } else { speed }
+    }
+
+    /// Restarts the ROM, as if the machine had been switched off and on again.
+    pub fn reset(&mut self) {
+        self.machine.reset();
+        self.display = Display::new(self.display.filters);
+        self.frame_budget = 0.0;
+        self.frames = 0;
+    }
+
+    /// Runs frames until the keypad asks to quit or to load another ROM.
+    pub fn run<V, K, A, T>(&mut self, video: &mut V, keypad: &mut K, audio: &mut A, timer: &mut T) -> Result<Exit, String>
+        where V: Video, K: Keypad, A: Audio, T: Timer
+    {
+        video.set_status(&self.status())?;
+
+        loop {
+            let mut advance = false;
+            while let Some(command) = keypad.poll() {
+                match command {
+                    Command::Quit => return Ok(Exit::Quit),
+                    Command::Load(name) => return Ok(Exit::Load(name)),
+                    Command::ToggleFullscreen => video.toggle_fullscreen()?,
+                    Command::TogglePause => self.paused = !self.paused,
+                    Command::FrameAdvance => {
+                        advance = self.paused;
+                        self.paused = true;
+                    },
+                    Command::SpeedUp => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+                    Command::SlowDown => self.speed = self.speed.saturating_sub(1),
+                    Command::NormalSpeed => self.speed = NORMAL_SPEED,
+                    Command::Reset => self.reset()
+                }
+                video.set_status(&self.status())?;
+            }
+
+            if let Some(overlay) = keypad.overlay() {
+                audio.set_tone(false);
+                video.present(&overlay)?;
+                timer.wait_for_frame();
+                continue;
+            }
+
+            let frames = if self.paused {
+                advance as u32
+            } else {
+                self.frame_budget += self.speed();
+                // The budget is never negative, so this is its floor
+                let frames = self.frame_budget as u32;
+                self.frame_budget -= frames as f32;
+                frames
+            };
+            for _ in 0 .. frames {
+                self.frame(keypad.held())?;
+            }
+
+            audio.set_tone(!self.paused && self.machine.state.sound_t > 0);
+            video.present(self.display.frame())?;
+            timer.wait_for_frame();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    use crate::cart::Cartridge;
+    use crate::cpu::KeyInput;
+
+    use super::*;
+
+    struct Capture {
+        frames: Vec<Frame>
+    }
+
+    impl Video for Capture {
+        fn present(&mut self, frame: &Frame) -> Result<(), String> {
+            self.frames.push(frame.clone());
+            Ok(())
+        }
+    }
+
+    /// Holds `key` for `frames` frames, then quits.
+    struct Script {
+        key: Option<u8>,
+        frames: usize,
+        /// Commands given at the start of each frame.
+        commands: Vec<Vec<Command>>
+    }
+
+    impl Script {
+        fn new(key: Option<u8>, frames: usize) -> Script {
+            Script { key, frames, commands: Vec::new() }
+        }
+    }
+
+    impl Keypad for Script {
+        fn poll(&mut self) -> Option<Command> {
+            if let Some(command) = self.commands.first_mut().and_then(|commands| commands.pop()) {
+                return Some(command);
+            }
+            if !self.commands.is_empty() {
+                self.commands.remove(0);
+            }
+
+            if self.frames == 0 {
+                return Some(Command::Quit);
+            }
+            self.frames -= 1;
+            None
+        }
+
+        fn held(&self) -> Option<u8> {
+            self.key
+        }
+    }
+
+    struct Buzzer {
+        tones: Vec<bool>
+    }
+
+    impl Audio for Buzzer {
+        fn set_tone(&mut self, on: bool) {
+            self.tones.push(on);
+        }
+    }
+
+    fn emulator(rom: &[u8], tickrate: u32) -> Emulator {
+        let cart = Cartridge::from_bytes(rom).unwrap();
+        Emulator::new(Machine::builder(&cart).build().unwrap(), tickrate)
+    }
+
+    #[test]
+    pub fn run_presents_one_frame_per_poll_until_quit() {
+        // LD V0, 0x5; LD F, V0; DRW V0, V0, 5; JP 0x206
+        let mut emulator = emulator(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06], 4);
+        let mut video = Capture { frames: Vec::new() };
+
+        let exit = emulator.run(&mut video, &mut Script::new(None, 3), &mut Silent, &mut Unthrottled).unwrap();
+
+        assert_eq!(exit, Exit::Quit);
+
+        assert_eq!(video.frames.len(), 3);
+        assert!((5 .. 9).all(|x| video.frames[0].is_lit(x, 5)));
+        assert!(!video.frames[0].is_lit(9, 5));
+        assert_eq!(emulator.frames, 3);
+    }
+
+    #[test]
+    pub fn speed_changes_frames_per_presented_frame() {
+        let mut emulator = emulator(&[0x12, 0x00], 1);
+        let mut video = Capture { frames: Vec::new() };
+        let mut keypad = Script::new(None, 4);
+        keypad.commands = vec![vec![Command::SpeedUp], vec![], vec![Command::SlowDown, Command::SlowDown]];
+
+        emulator.run(&mut video, &mut keypad, &mut Silent, &mut Unthrottled).unwrap();
+
+        // 2 + 2 frames at 200%, then 0 + 1 at 50%
+        assert_eq!(emulator.frames, 5);
+        assert_eq!(emulator.status(), "50%");
+    }
+
+    #[test]
+    pub fn pause_stops_frames_until_advanced() {
+        let mut emulator = emulator(&[0x12, 0x00], 1);
+        let mut video = Capture { frames: Vec::new() };
+        let mut keypad = Script::new(None, 4);
+        keypad.commands = vec![vec![Command::TogglePause], vec![], vec![Command::FrameAdvance], vec![Command::Load("next.ch8".to_string())]];
+
+        let exit = emulator.run(&mut video, &mut keypad, &mut Silent, &mut Unthrottled).unwrap();
+
+        assert_eq!(exit, Exit::Load("next.ch8".to_string()));
+        assert_eq!(emulator.frames, 1);
+        assert_eq!(video.frames.len(), 3);
+        assert_eq!(emulator.status(), "100% - paused");
+    }
+
+    #[test]
+    pub fn reset_restarts_the_rom() {
+        // LD V0, 0x1; JP 0x202
+        let mut emulator = emulator(&[0x60, 0x01, 0x12, 0x02], 2);
+        let mut keypad = Script::new(None, 2);
+        keypad.commands = vec![vec![], vec![Command::Reset]];
+
+        emulator.run(&mut Capture { frames: Vec::new() }, &mut keypad, &mut Silent, &mut Unthrottled).unwrap();
+
+        assert_eq!(emulator.frames, 1);
+        assert_eq!(emulator.machine.state.vreg[0x0], 0x1);
+    }
+
+    #[test]
+    pub fn vblank_only_shows_the_screen_when_the_program_waits() {
+        // Draws the top row of the "0" glyph, polls the delay timer, then erases the glyph again:
+        // DRW V0, V0, 1; LD V1, DT; DRW V0, V0, 1; JP 0x200
+        let rom = [0xD0, 0x01, 0xF1, 0x07, 0xD0, 0x01, 0x12, 0x00];
+        let mut emulator = emulator(&rom, 3);
+
+        emulator.frame(None).unwrap();
+        assert!(!emulator.display.frame().is_lit(0, 0));
+
+        let mut emulator = emulator.filters("vblank".parse().unwrap());
+        emulator.machine.reset();
+
+        emulator.frame(None).unwrap();
+        assert!(emulator.display.frame().is_lit(0, 0));
+    }
+
+    #[test]
+    pub fn integer_scaling_centres_whole_multiples() {
+        assert_eq!(Scaling::Integer.viewport((800, 600), (64, 32)), (16, 108, 768, 384));
+        assert_eq!(Scaling::Integer.viewport((32, 16), (64, 32)), (-16, -8, 64, 32));
+    }
+
+    #[test]
+    pub fn aspect_scaling_letterboxes_the_short_side() {
+        assert_eq!(Scaling::Aspect.viewport((800, 600), (64, 32)), (0, 100, 800, 400));
+        assert_eq!(Scaling::Aspect.viewport((1000, 300), (64, 32)), (200, 0, 600, 300));
+        assert_eq!(Scaling::Stretch.viewport((1000, 300), (64, 32)), (0, 0, 1000, 300));
+    }
+
+    #[test]
+    pub fn held_key_reaches_the_machine() {
+        // LD V1, K; LD ST, V1; JP 0x204
+        let mut emulator = emulator(&[0xF1, 0x0A, 0xF1, 0x18, 0x12, 0x04], 2);
+        let mut buzzer = Buzzer { tones: Vec::new() };
+        let mut video = Capture { frames: Vec::new() };
+
+        emulator.run(&mut video, &mut Script::new(Some(0x2), 2), &mut buzzer, &mut Unthrottled).unwrap();
+
+        assert_eq!(emulator.machine.state.vreg[0x1], 0x2);
+        assert_eq!(buzzer.tones, vec![true, false]);
+    }
+
+    #[test]
+    pub fn keypad_given_to_the_machine_is_kept() {
+        struct Always(u8);
+        impl KeyInput for Always {
+            fn held(&self) -> Option<u8> {
+                Some(self.0)
+            }
+        }
+        // LD V1, K; JP 0x202
+        let cart = Cartridge::from_bytes(&[0xF1, 0x0A, 0x12, 0x02]).unwrap();
+        let machine = Machine::builder(&cart).keypad(Rc::new(Always(0x7))).build().unwrap();
+        let mut emulator = Emulator::new(machine, 2);
+
+        emulator.frame(None).unwrap();
+
+        assert_eq!(emulator.machine.state.vreg[0x1], 0x7);
+    }
+
+    #[test]
+    pub fn trace_writes_a_line_per_instruction() {
+        struct Shared(Rc<RefCell<String>>);
+        impl fmt::Write for Shared {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.borrow_mut().push_str(s);
+                Ok(())
+            }
+        }
+        let lines = Rc::new(RefCell::new(String::new()));
+        // LD V0, 0x1; JP 0x202
+        let mut emulator = emulator(&[0x60, 0x01, 0x12, 0x02], 3)
+            .trace(Box::new(FmtTrace(Shared(lines.clone()))));
+
+        emulator.frame(None).unwrap();
+
+        let lines = lines.borrow();
+        assert_eq!(lines.lines().count(), 3);
+        assert!(lines.starts_with("ProcState: PC=0x202"));
+        assert!(lines.contains("Instruction: JP 0x202"));
+    }
+}
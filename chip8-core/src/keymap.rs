@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::display::Frame;
+use crate::font::{FONT_SPRITES, SMALL_GLYPH_SIZE};
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Keypad keys in the order they appear on the 4x4 COSMAC VIP keypad, row by row.
+pub const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF
+];
+
+/// The host keys in the same positions as the keypad on a QWERTY keyboard.
+pub const QWERTY_LAYOUT: &str = "1234QWERASDFZXCV";
+
+/// Host inputs mapped to keypad keys. Inputs are named by the frontend, e.g. `Q`, `Up` or
+/// `pad:dpup`, and matched without regard to case.
+///
+/// In config files a keymap is either an object of input names to keys or a layout: 16
+/// characters naming the host key for each keypad position, row by row, e.g. `1234AZERQSDFWXCV`
+/// for AZERTY keyboards.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "KeymapRepr", into = "BTreeMap<String, u8>")]
+pub struct Keymap {
+    bindings: BTreeMap<String, u8>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeymapRepr {
+    Layout(String),
+    Bindings(BTreeMap<String, u8>)
+}
+
+impl TryFrom<KeymapRepr> for Keymap {
+    type Error = String;
+
+    fn try_from(repr: KeymapRepr) -> Result<Self, Self::Error> {
+        match repr {
+            KeymapRepr::Layout(layout) => layout.parse(),
+            KeymapRepr::Bindings(bindings) => {
+                let mut keymap = Keymap::default();
+                for (input, key) in bindings {
+                    if key > 0xF {
+                        return Err(format!("key {} for {} is not on the keypad", key, input));
+                    }
+                    keymap.bind(&input, key);
+                }
+                Ok(keymap)
+            }
+        }
+    }
+}
+
+impl From<Keymap> for BTreeMap<String, u8> {
+    fn from(keymap: Keymap) -> Self {
+        keymap.bindings
+    }
+}
+
+impl Keymap {
+    pub fn qwerty() -> Keymap {
+        QWERTY_LAYOUT.parse().expect("QWERTY layout is valid")
+    }
+
+    pub fn get(&self, input: &str) -> Option<u8> {
+        self.bindings.get(&input.to_ascii_lowercase()).copied()
+    }
+
+    /// Binds `input` to `key`, replacing whatever `input` was bound to before.
+    pub fn bind(&mut self, input: &str, key: u8) {
+        self.bindings.insert(input.to_ascii_lowercase(), key);
+    }
+
+    /// Adds the bindings of `other`, which win over any existing binding of the same input.
+    pub fn extend(&mut self, other: &Keymap) {
+        self.bindings.extend(other.bindings.iter().map(|(input, key)| (input.clone(), *key)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.bindings.iter().map(|(input, key)| (input.as_str(), *key))
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if chars.len() != KEYPAD_ORDER.len() {
+            return Err(format!("a layout needs {} keys, got {}", KEYPAD_ORDER.len(), chars.len()));
+        }
+        if let Some((_, c)) = chars.iter().enumerate().find(|(i, c)| chars[.. *i].contains(c)) {
+            return Err(format!("{} is used for more than one key", c));
+        }
+
+        let mut keymap = Keymap::default();
+        for (c, key) in chars.iter().zip(KEYPAD_ORDER.iter()) {
+            keymap.bind(&c.to_string(), *key);
+        }
+        Ok(keymap)
+    }
+}
+
+/// Walks the user through the keypad one key at a time, recording which host input they press
+/// for each.
+pub struct Remapper {
+    position: usize,
+    keymap: Keymap
+}
+
+impl Remapper {
+    pub fn new() -> Remapper {
+        Remapper { position: 0, keymap: Keymap::default() }
+    }
+
+    /// The keypad key waiting for an input, or `None` once every key has had its turn.
+    pub fn key(&self) -> Option<u8> {
+        KEYPAD_ORDER.get(self.position).copied()
+    }
+
+    pub fn bind(&mut self, input: &str) {
+        if let Some(key) = self.key() {
+            self.keymap.bind(input, key);
+            self.position += 1;
+        }
+    }
+
+    /// Leaves the current key as it was.
+    pub fn skip(&mut self) {
+        self.position += 1;
+    }
+
+    /// The new bindings made so far.
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// The keypad drawn as a 4x4 grid, with the key waiting for an input shown inverted.
+    pub fn frame(&self) -> Frame {
+        let cell_width = SCREEN_WIDTH / 4;
+        let cell_height = SCREEN_HEIGHT / 4;
+        let mut buffer = [0x0u64; SCREEN_HEIGHT];
+
+        for (position, key) in KEYPAD_ORDER.iter().enumerate() {
+            let left = (position % 4) * cell_width;
+            let top = (position / 4) * cell_height;
+            let glyph = &FONT_SPRITES[*key as usize * SMALL_GLYPH_SIZE ..][.. SMALL_GLYPH_SIZE];
+            let glyph_left = left + (cell_width - 4) / 2;
+            let glyph_top = top + (cell_height - SMALL_GLYPH_SIZE) / 2;
+
+            for (row, line) in buffer.iter_mut().enumerate().skip(top).take(cell_height) {
+                let mut pixels = if glyph_top <= row && row < glyph_top + SMALL_GLYPH_SIZE {
+                    ((glyph[row - glyph_top] >> 4) as u64) << (SCREEN_WIDTH - 4 - glyph_left)
+                } else {
+                    0x0
+                };
+                if self.key() == Some(*key) {
+                    let cell_mask = (u64::MAX >> (SCREEN_WIDTH - cell_width)) << (SCREEN_WIDTH - cell_width - left);
+                    pixels ^= cell_mask;
+                }
+                *line |= pixels;
+            }
+        }
+
+        Frame::from_rows(&buffer)
+    }
+}
+
+impl Default for Remapper {
+    fn default() -> Self {
+        Remapper::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn qwerty_layout_matches_keypad_positions() {
+        let keymap = Keymap::qwerty();
+
+        assert_eq!(keymap.get("1"), Some(0x1));
+        assert_eq!(keymap.get("r"), Some(0xD));
+        assert_eq!(keymap.get("X"), Some(0x0));
+        assert_eq!(keymap.get("P"), None);
+    }
+
+    #[test]
+    pub fn layouts_need_sixteen_distinct_keys() {
+        assert!("1234".parse::<Keymap>().is_err());
+        assert!("1234qwerasdfzxcc".parse::<Keymap>().is_err());
+        assert_eq!("1234azerqsdfwxcv".parse::<Keymap>().unwrap().get("W"), Some(0xA));
+    }
+
+    #[test]
+    pub fn remapper_asks_for_every_key_in_keypad_order() {
+        let mut remapper = Remapper::new();
+        assert_eq!(remapper.key(), Some(0x1));
+
+        remapper.bind("Kp7");
+        remapper.skip();
+        assert_eq!(remapper.key(), Some(0x3));
+        for _ in 0 .. 14 {
+            remapper.bind("X");
+        }
+
+        assert_eq!(remapper.key(), None);
+        assert_eq!(remapper.keymap().get("kp7"), Some(0x1));
+        assert_eq!(remapper.keymap().get("x"), Some(0xF));
+    }
+
+    #[test]
+    pub fn remapper_highlights_the_current_key() {
+        let mut remapper = Remapper::new();
+        remapper.bind("A");
+
+        let frame = remapper.frame();
+        // The "2" cell is the second in the top row and is drawn inverted
+        assert!(frame.is_lit(16, 0) && frame.is_lit(31, 7));
+        assert!(!frame.is_lit(15, 0) && !frame.is_lit(32, 0));
+        // The top row of the "1" glyph in the first cell
+        assert!(frame.is_lit(8, 1) && !frame.is_lit(6, 1));
+    }
+}
@@ -1,6 +1,32 @@
+//! The CHIP-8 machine and everything around it. Without the default `std` feature the core
+//! builds with only `alloc`, taking keys, random numbers and ROMs through `cpu::KeyInput`,
+//! `cpu::Entropy` and `cart::RomInput`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod cpu;
 pub mod cart;
 pub mod font;
+pub mod frontend;
+#[cfg(feature = "std")]
+pub mod keymap;
+pub mod machine;
+pub mod color;
+#[cfg(feature = "std")]
+pub mod db;
+pub mod display;
+pub mod quirks;
+pub mod recompiler;
+#[cfg(feature = "std")]
+pub mod settings;
+pub mod sha1;
+#[cfg(feature = "std")]
+pub mod source;
+pub mod timing;
+#[cfg(feature = "std")]
+pub mod vectors;
 
 pub const SCREEN_WIDTH: usize = 64;
-pub const SCREEN_HEIGHT: usize = 32;
\ No newline at end of file
+pub const SCREEN_HEIGHT: usize = 32;
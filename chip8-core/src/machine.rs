@@ -0,0 +1,500 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+
+use crate::cart::Cartridge;
+use crate::cpu::opcodes::Opcode;
+use crate::cpu::{Entropy, Fault, KeyInput, ProcState, MAX_MEMORY_SIZE, STARTING_PROGRAM_COUNTER};
+use crate::font::Font;
+use crate::quirks::{Platform, Quirks};
+use crate::recompiler::Recompiler;
+use crate::timing;
+
+#[derive(Debug, PartialEq)]
+pub enum MachineError {
+    RomDoesNotFit { load_address: u16, size: usize },
+    FontDoesNotFit { font_address: u16, size: usize },
+    FontOverlapsRom,
+    /// Neither a seed nor an entropy source was given, which a build without std needs for `RND`.
+    NoRandomSource
+}
+
+impl Display for MachineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::RomDoesNotFit{load_address, size} => {
+                write!(f, "a {} byte ROM does not fit in memory at {:#05x}", size, load_address)
+            },
+            MachineError::FontDoesNotFit{font_address, size} => {
+                write!(f, "a {} byte font does not fit in memory at {:#05x}", size, font_address)
+            },
+            MachineError::FontOverlapsRom => write!(f, "the font overlaps the ROM"),
+            MachineError::NoRandomSource => write!(f, "RND needs a seed or an entropy source")
+        }
+    }
+}
+
+impl Error for MachineError {}
+
+/// A `ProcState` together with the memory image it was booted from, so it can be reset.
+pub struct Machine {
+    pub state: ProcState,
+    pub platform: Option<Platform>,
+    image: [u8; MAX_MEMORY_SIZE],
+    load_address: u16,
+    rom_size: usize,
+    seed: Option<u64>,
+    /// The keypad made for the machine when none was given to the builder.
+    keys: Option<Rc<Cell<Option<u8>>>>,
+    recompiler: Option<Recompiler>,
+    vip_timing: bool
+}
+
+impl Machine {
+    pub fn builder(cart: &Cartridge) -> MachineBuilder {
+        MachineBuilder {
+            rom: cart.buffer[.. cart.size].to_vec(),
+            platform: None,
+            quirks: None,
+            font: Font::default(),
+            font_address: 0x0,
+            load_address: STARTING_PROGRAM_COUNTER,
+            io_queue: None,
+            entropy: None,
+            seed: None,
+            decode_cache: false,
+            recompiler: false,
+            vip_timing: false
+        }
+    }
+
+    /// Restores memory to the original ROM and font and clears all registers, timers, the stack
+    /// and the screen. The keypad, entropy source, quirks and font address are kept, and a seeded
+    /// random number generator starts over.
+    pub fn reset(&mut self) {
+        let mut state = ProcState::new(self.image, self.state.io_queue.clone());
+        state.entropy = self.state.entropy.clone();
+        state.pc = self.load_address;
+        state.quirks = self.state.quirks;
+        state.font_address = self.state.font_address;
+        if let Some(seed) = self.seed {
+            state.seed(seed);
+        }
+        if self.state.decode_cache_enabled() {
+            state.enable_decode_cache();
+        }
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.invalidate();
+        }
+        self.state = state;
+    }
+
+    /// Holds `key` down, or releases every key for `None`. Keys given through
+    /// `MachineBuilder::keypad` are read from there instead, so this does nothing.
+    pub fn set_key(&mut self, key: Option<u8>) {
+        if let Some(keys) = &self.keys {
+            keys.set(key);
+        }
+    }
+
+    /// Whether instructions take their COSMAC VIP time, see `MachineBuilder::vip_timing`.
+    pub fn vip_timing(&self) -> bool {
+        self.vip_timing
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    pub fn step(&mut self) -> Opcode {
+        match self.try_step() {
+            Ok(opcode) => opcode,
+            Err(fault) => panic!("{}: {}", fault, &self.state)
+        }
+    }
+
+    /// Like `step`, but reports a fault in the ROM instead of panicking.
+    pub fn try_step(&mut self) -> Result<Opcode, Fault> {
+        if self.vip_timing {
+            return self.timed_step(timing::next_interrupt(self.state.clock));
+        }
+        if self.recompiler.is_some() {
+            let mut executed = None;
+            self.run(1, |_, opcode| executed = Some(opcode))?;
+            return Ok(executed.expect("one instruction runs"));
+        }
+
+        let opcode = self.state.fetch_and_decode_opcode();
+        self.state.try_execute_opcode(opcode)?;
+        Ok(opcode)
+    }
+
+    /// Executes up to `budget` instructions, calling `on_step` with the state after each, and
+    /// stops early on a fault.
+    pub fn run<F: FnMut(&ProcState, Opcode)>(&mut self, budget: u32, mut on_step: F) -> Result<(), Fault> {
+        if let Some(recompiler) = &mut self.recompiler {
+            return recompiler.run(&mut self.state, budget, on_step);
+        }
+
+        for _ in 0 .. budget {
+            let opcode = self.try_step()?;
+            on_step(&self.state, opcode);
+        }
+        Ok(())
+    }
+
+    /// Under VIP timing, executes instructions until the next display interrupt, calling
+    /// `on_step` with the state after each, and then runs the interrupt: the timers count down
+    /// and the interpreter loses `INTERRUPT_CYCLES` to it. Stops early on a fault.
+    pub fn run_frame<F: FnMut(&ProcState, Opcode)>(&mut self, mut on_step: F) -> Result<(), Fault> {
+        let interrupt = timing::next_interrupt(self.state.clock);
+        while self.state.clock < interrupt {
+            let opcode = self.timed_step(interrupt)?;
+            on_step(&self.state, opcode);
+        }
+
+        self.state.clock_tick(1);
+        self.state.clock += timing::INTERRUPT_CYCLES;
+        Ok(())
+    }
+
+    /// Executes an instruction and adds its VIP cycles to the clock. A `DRW` waiting for the
+    /// display interrupt idles until `interrupt` instead.
+    fn timed_step(&mut self, interrupt: u64) -> Result<Opcode, Fault> {
+        let pc = self.state.pc;
+        let opcode = self.state.fetch_and_decode_opcode();
+        let cycles = timing::cycles(&self.state, opcode);
+        self.state.try_execute_opcode(opcode)?;
+
+        // A waiting DRW leaves the program counter on itself
+        if matches!(opcode, Opcode::DRW{..}) && self.state.pc == pc {
+            self.state.clock = self.state.clock.max(interrupt);
+        } else {
+            self.state.clock += cycles;
+        }
+        Ok(opcode)
+    }
+
+    /// Must be called after writing to `state.mem` directly, so compiled code is not stale.
+    pub fn invalidate_code(&mut self) {
+        self.state.invalidate_decode_cache();
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.invalidate();
+        }
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// The ROM image as originally loaded, regardless of any self-modification since.
+    pub fn rom(&self) -> &[u8] {
+        let start = self.load_address as usize;
+        &self.image[start .. start + self.rom_size]
+    }
+}
+
+pub struct MachineBuilder {
+    rom: Vec<u8>,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    font: Font,
+    font_address: u16,
+    load_address: u16,
+    io_queue: Option<Rc<dyn KeyInput>>,
+    entropy: Option<Rc<dyn Entropy>>,
+    seed: Option<u64>,
+    decode_cache: bool,
+    recompiler: bool,
+    vip_timing: bool
+}
+
+impl MachineBuilder {
+    /// Selects the platform whose quirks are used unless `quirks` overrides them.
+    pub fn platform(mut self, platform: Option<Platform>) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    pub fn font(mut self, font: Font) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// Where the font is loaded, 0 unless changed.
+    pub fn font_address(mut self, font_address: u16) -> Self {
+        self.font_address = font_address;
+        self
+    }
+
+    pub fn load_address(mut self, load_address: u16) -> Self {
+        self.load_address = load_address;
+        self
+    }
+
+    pub fn keypad(mut self, io_queue: Rc<dyn KeyInput>) -> Self {
+        self.io_queue = Some(io_queue);
+        self
+    }
+
+    /// Where unseeded `RND` draws its bytes from, instead of the clock. Without std there is
+    /// no clock, so `build` needs this or a seed.
+    pub fn entropy(mut self, entropy: Rc<dyn Entropy>) -> Self {
+        self.entropy = Some(entropy);
+        self
+    }
+
+    /// Seeds `RND` so runs are repeatable, rather than drawing on the clock.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Decodes each instruction once rather than on every fetch, see
+    /// `ProcState::enable_decode_cache`.
+    pub fn decode_cache(mut self, decode_cache: bool) -> Self {
+        self.decode_cache = decode_cache;
+        self
+    }
+
+    /// Runs the ROM through the `Recompiler` rather than the interpreter.
+    pub fn recompiler(mut self, recompiler: bool) -> Self {
+        self.recompiler = recompiler;
+        self
+    }
+
+    /// Makes every instruction take as many machine cycles as on the COSMAC VIP, counted by
+    /// `ProcState::clock`, with `Machine::run_frame` running the instructions that fit between
+    /// two display interrupts. The instructions are always interpreted, so this takes precedence
+    /// over `recompiler`.
+    pub fn vip_timing(mut self, vip_timing: bool) -> Self {
+        self.vip_timing = vip_timing;
+        self
+    }
+
+    pub fn build(self) -> Result<Machine, MachineError> {
+        // There is no clock to fall back on
+        #[cfg(not(feature = "std"))]
+        if self.seed.is_none() && self.entropy.is_none() {
+            return Err(MachineError::NoRandomSource);
+        }
+
+        let start = self.load_address as usize;
+        let end = start + self.rom.len();
+        if end > MAX_MEMORY_SIZE {
+            return Err(MachineError::RomDoesNotFit { load_address: self.load_address, size: self.rom.len() });
+        }
+
+        let font = self.font.bytes();
+        let font_start = self.font_address as usize;
+        let font_end = font_start + font.len();
+        if font_end > MAX_MEMORY_SIZE {
+            return Err(MachineError::FontDoesNotFit { font_address: self.font_address, size: font.len() });
+        }
+        if font_start < end && start < font_end {
+            return Err(MachineError::FontOverlapsRom);
+        }
+
+        let mut image = [0x0; MAX_MEMORY_SIZE];
+        image[start .. end].copy_from_slice(&self.rom);
+        image[font_start .. font_end].copy_from_slice(&font);
+
+        let quirks = self.quirks
+            .or_else(|| self.platform.map(|p| p.quirks()))
+            .unwrap_or_default();
+        let (io_queue, keys): (Rc<dyn KeyInput>, _) = match self.io_queue {
+            Some(io_queue) => (io_queue, None),
+            None => {
+                let keys = Rc::new(Cell::new(Option::None));
+                (keys.clone(), Some(keys))
+            }
+        };
+
+        let mut state = ProcState::new(image, io_queue);
+        state.entropy = self.entropy;
+        state.pc = self.load_address;
+        state.quirks = quirks;
+        state.font_address = self.font_address;
+        if let Some(seed) = self.seed {
+            state.seed(seed);
+        }
+        if self.decode_cache {
+            state.enable_decode_cache();
+        }
+
+        Ok(Machine {
+            state,
+            platform: self.platform,
+            image,
+            load_address: self.load_address,
+            rom_size: self.rom.len(),
+            seed: self.seed,
+            keys,
+            recompiler: if self.recompiler && !self.vip_timing { Some(Recompiler::new()) } else { None },
+            vip_timing: self.vip_timing
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cart::Cartridge;
+    use crate::cpu::opcodes::Opcode;
+    use crate::font::{BigFont, SmallFont, FONT_SPRITES};
+    use crate::quirks::Platform;
+
+    use super::*;
+
+    fn cart(rom: &[u8]) -> Cartridge {
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    pub fn build_loads_rom_and_font() {
+        let machine = Machine::builder(&cart(&[0x00, 0xE0])).build().unwrap();
+
+        assert_eq!(&machine.state.mem[0x200 .. 0x202], &[0x00, 0xE0]);
+        assert_eq!(&machine.state.mem[.. FONT_SPRITES.len()], &FONT_SPRITES[..]);
+        assert_eq!(machine.state.pc, 0x200);
+    }
+
+    #[test]
+    pub fn build_honours_load_address_and_platform() {
+        let machine = Machine::builder(&cart(&[0x12, 0x34]))
+            .load_address(0x600)
+            .platform(Some(Platform::OriginalChip8))
+            .build()
+            .unwrap();
+
+        assert_eq!(machine.state.pc, 0x600);
+        assert_eq!(machine.rom(), &[0x12, 0x34]);
+        assert_eq!(machine.state.quirks, Platform::OriginalChip8.quirks());
+    }
+
+    #[test]
+    pub fn build_rejects_rom_past_end_of_memory() {
+        let result = Machine::builder(&cart(&[0x0; 0x10])).load_address(0xFF8).build();
+
+        assert_eq!(result.err(), Some(MachineError::RomDoesNotFit { load_address: 0xFF8, size: 0x10 }));
+    }
+
+    #[test]
+    pub fn build_rejects_font_overlapping_rom() {
+        let result = Machine::builder(&cart(&[0x0])).load_address(0x10).build();
+
+        assert_eq!(result.err(), Some(MachineError::FontOverlapsRom));
+    }
+
+    #[test]
+    pub fn build_places_font_at_font_address() {
+        let font = Font::from(SmallFont::CosmacVip).with_big(BigFont::SuperChip);
+        let machine = Machine::builder(&cart(&[0x0]))
+            .font(font.clone())
+            .font_address(0x50)
+            .build()
+            .unwrap();
+
+        assert_eq!(&machine.state.mem[0x50 .. 0x50 + 180], &font.bytes()[..]);
+        assert_eq!(machine.state.mem[0x0], 0x0);
+        assert_eq!(machine.state.font_address, 0x50);
+    }
+
+    #[test]
+    pub fn reset_restores_rom_font_and_registers() {
+        // LD I, 0x200; LD V0, 0xFF; LD [I], V0
+        let mut machine = Machine::builder(&cart(&[0xA2, 0x00, 0x60, 0xFF, 0xF0, 0x55])).build().unwrap();
+        for _ in 0 .. 3 {
+            machine.step();
+        }
+        machine.state.mem[0x0] = 0x0;
+        assert_eq!(machine.state.mem[0x200], 0xFF);
+
+        machine.reset();
+
+        assert_eq!(machine.state.mem[0x200], 0xA2);
+        assert_eq!(machine.state.mem[0x0], FONT_SPRITES[0]);
+        assert_eq!(machine.state.vreg[0x0], 0x0);
+        assert_eq!(machine.state.pc, 0x200);
+        assert_eq!(machine.step(), Opcode::LDIAddr { addr: 0x200 });
+    }
+
+    #[test]
+    pub fn decode_cache_sees_self_modifying_code() {
+        // 0x200: LD V2, 0x05; LD I, 0x200; LD V0, 0x62; ADD V1, 0x01; LD [I], V1; JP 0x200,
+        // where the store rewrites the first instruction to LD V2, <value of V1>, one higher
+        // every time round
+        let rom = [0x62, 0x05, 0xA2, 0x00, 0x60, 0x62, 0x71, 0x01, 0xF1, 0x55, 0x12, 0x00];
+        let run = |decode_cache| {
+            let mut machine = Machine::builder(&cart(&rom)).decode_cache(decode_cache).build().unwrap();
+            let trace: Vec<(u16, Opcode)> = (0 .. 20)
+                .map(|_| (machine.state.pc, machine.step()))
+                .collect();
+            (trace, machine.state.mem.to_vec())
+        };
+
+        let (uncached, _) = run(false);
+        assert_eq!(uncached[12], (0x200, Opcode::LDVxByte { x: 0x2, byte: 0x2 }));
+        assert_eq!(run(true), run(false));
+    }
+
+    #[test]
+    pub fn decode_cache_survives_reset() {
+        let mut machine = Machine::builder(&cart(&[0x12, 0x00])).decode_cache(true).build().unwrap();
+        machine.reset();
+
+        assert!(machine.state.decode_cache_enabled());
+    }
+
+    #[test]
+    pub fn try_step_reports_faults() {
+        // RET with nothing on the stack, then 0xFFFF
+        let mut machine = Machine::builder(&cart(&[0x00, 0xEE, 0xFF, 0xFF])).build().unwrap();
+
+        assert_eq!(machine.try_step(), Err(Fault::StackUnderflow));
+        assert_eq!(machine.try_step(), Err(Fault::UnknownOpcode(Opcode::UNKNOWN { opcode: (0xF, 0xF, 0xF, 0xF) })));
+        assert_eq!(machine.state.pc, 0x204);
+    }
+
+    #[test]
+    pub fn run_frame_fits_instructions_between_interrupts() {
+        // JP 0x200, 23 cycles each
+        let mut machine = Machine::builder(&cart(&[0x12, 0x00])).vip_timing(true).build().unwrap();
+
+        let mut steps = 0;
+        machine.run_frame(|_, _| steps += 1).unwrap();
+        assert_eq!(steps, 160);
+        assert_eq!(machine.state.clock, 160 * 23 + timing::INTERRUPT_CYCLES);
+
+        // The interrupt leaves less of the next frame to the interpreter
+        steps = 0;
+        machine.run_frame(|_, _| steps += 1).unwrap();
+        assert_eq!(steps, 113);
+    }
+
+    #[test]
+    pub fn drw_idles_until_the_display_interrupt() {
+        // DRW V0, V0, 1; JP 0x202
+        let mut machine = Machine::builder(&cart(&[0xD0, 0x01, 0x12, 0x02]))
+            .platform(Some(Platform::OriginalChip8))
+            .vip_timing(true)
+            .build()
+            .unwrap();
+        machine.state.delay_t = 2;
+
+        machine.run_frame(|_, _| ()).unwrap();
+        assert_eq!(machine.state.video_buffer[0], 0x0);
+        assert_eq!(machine.state.clock, timing::CYCLES_PER_FRAME + timing::INTERRUPT_CYCLES);
+        assert_eq!(machine.state.delay_t, 1);
+
+        let mut executed = Vec::new();
+        machine.run_frame(|state, opcode| executed.push((state.clock, opcode))).unwrap();
+        assert_eq!(machine.state.video_buffer[0] >> 56, 0xF0);
+        assert_eq!(executed[0].0, timing::CYCLES_PER_FRAME + timing::INTERRUPT_CYCLES + timing::draw_cycles(0, 1));
+        assert_eq!(machine.state.delay_t, 0);
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+use core::fmt;
+use core::str::FromStr;
+
+/// Behavioural differences between CHIP-8 interpreters. Field names follow the community
+/// chip-8-database so ROM metadata can be applied directly.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Quirks {
+    /// `SHR`/`SHL` shift Vx in place instead of loading the shifted Vy into Vx.
+    pub shift: bool,
+    /// `LD [I], Vx` and `LD Vx, [I]` advance I by x instead of x + 1.
+    pub memory_increment_by_x: bool,
+    /// `LD [I], Vx` and `LD Vx, [I]` leave I unchanged.
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the screen edges instead of being clipped.
+    pub wrap: bool,
+    /// `JP V0, addr` jumps to addr + Vx, where x is the high nibble of addr.
+    pub jump: bool,
+    /// `DRW` waits for the next 60Hz tick before drawing.
+    pub vblank: bool,
+    /// `OR`, `AND` and `XOR` reset VF to zero.
+    pub logic: bool
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false
+        }
+    }
+}
+
+impl Quirks {
+    /// The names used by the chip-8-database, in field order.
+    pub const NAMES: [&'static str; 7] = [
+        "shift", "memoryIncrementByX", "memoryLeaveIUnchanged", "wrap", "jump", "vblank", "logic"
+    ];
+
+    /// Turns the quirk with the given database name on or off.
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match Quirks::NAMES.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+            Some(0) => &mut self.shift,
+            Some(1) => &mut self.memory_increment_by_x,
+            Some(2) => &mut self.memory_leave_i_unchanged,
+            Some(3) => &mut self.wrap,
+            Some(4) => &mut self.jump,
+            Some(5) => &mut self.vblank,
+            Some(6) => &mut self.logic,
+            _ => return Err(format!("unknown quirk: {}", name))
+        };
+        *quirk = value;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Platform {
+    OriginalChip8,
+    HybridVip,
+    ModernChip8,
+    Chip48,
+    SuperChip1,
+    SuperChip,
+    XoChip
+}
+
+impl Platform {
+    pub const ALL: [Platform; 7] = [
+        Platform::OriginalChip8,
+        Platform::HybridVip,
+        Platform::ModernChip8,
+        Platform::Chip48,
+        Platform::SuperChip1,
+        Platform::SuperChip,
+        Platform::XoChip
+    ];
+
+    /// The identifier used for this platform by the community chip-8-database.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "originalChip8",
+            Platform::HybridVip => "hybridVIP",
+            Platform::ModernChip8 => "modernChip8",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip1 => "superchip1",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip"
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip => Quirks {
+                shift: false,
+                memory_increment_by_x: false,
+                memory_leave_i_unchanged: false,
+                wrap: false,
+                jump: false,
+                vblank: true,
+                logic: true
+            },
+            Platform::ModernChip8 => Quirks {
+                shift: false,
+                memory_increment_by_x: false,
+                memory_leave_i_unchanged: false,
+                wrap: false,
+                jump: false,
+                vblank: false,
+                logic: false
+            },
+            Platform::Chip48 | Platform::SuperChip1 => Quirks {
+                shift: true,
+                memory_increment_by_x: true,
+                memory_leave_i_unchanged: false,
+                wrap: false,
+                jump: true,
+                vblank: false,
+                logic: false
+            },
+            Platform::SuperChip => Quirks {
+                shift: true,
+                memory_increment_by_x: false,
+                memory_leave_i_unchanged: true,
+                wrap: false,
+                jump: true,
+                vblank: false,
+                logic: false
+            },
+            Platform::XoChip => Quirks {
+                shift: false,
+                memory_increment_by_x: false,
+                memory_leave_i_unchanged: false,
+                wrap: true,
+                jump: false,
+                vblank: false,
+                logic: false
+            }
+        }
+    }
+
+    /// The platform conventionally implied by a ROM file extension. `.ch8` implies none, so
+    /// plain CHIP-8 ROMs keep the default quirks, and `.xo8` implies none until the XO-CHIP
+    /// instructions are supported.
+    pub fn from_extension(ext: &str) -> Option<Platform> {
+        match ext.to_ascii_lowercase().as_str() {
+            "sc8" => Some(Platform::SuperChip),
+            _ => None
+        }
+    }
+
+    /// Instructions executed per 60Hz frame on this platform.
+    pub fn tickrate(&self) -> u32 {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip => 15,
+            Platform::ModernChip8 => 12,
+            Platform::Chip48 | Platform::SuperChip1 | Platform::SuperChip => 30,
+            Platform::XoChip => 100
+        }
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Platform::ALL.iter()
+            .find(|p| p.id().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown platform: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn platform_round_trips_through_its_id() {
+        for platform in Platform::ALL.iter() {
+            assert_eq!(platform.id().parse::<Platform>(), Ok(*platform));
+        }
+    }
+
+    #[test]
+    pub fn rom_extensions_imply_platform() {
+        assert_eq!(Platform::from_extension("SC8"), Some(Platform::SuperChip));
+        assert_eq!(Platform::from_extension("ch8"), None);
+        assert_eq!(Platform::from_extension("xo8"), None);
+        assert_eq!(Platform::from_extension("bin"), None);
+    }
+
+    #[test]
+    pub fn quirks_are_set_by_database_name() {
+        let mut quirks = Quirks::default();
+
+        quirks.set("memoryIncrementByX", true).unwrap();
+        quirks.set("SHIFT", false).unwrap();
+
+        assert!(quirks.memory_increment_by_x && !quirks.shift);
+        assert!(quirks.set("fastDraw", true).is_err());
+    }
+
+    #[test]
+    pub fn unknown_platform_is_rejected() {
+        assert!("megachip8".parse::<Platform>().is_err());
+    }
+}
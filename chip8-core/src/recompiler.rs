@@ -0,0 +1,410 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::vec;
+use crate::cpu::metadata::Flow;
+use crate::cpu::opcodes::{get_opcode, Opcode};
+use crate::cpu::{Fault, ProcState, MAX_MEMORY_SIZE};
+
+/// Most instructions compiled into one block.
+const MAX_BLOCK_LEN: usize = 64;
+
+/// An instruction with its operands unpacked. The common register, timer, jump and skip
+/// instructions that no quirk affects run directly, everything else goes through the interpreter.
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum MicroOp {
+    SetByte { x: usize, byte: u8 },
+    AddByte { x: usize, byte: u8 },
+    Copy { x: usize, y: usize },
+    Add { x: usize, y: usize },
+    Sub { x: usize, y: usize },
+    SubN { x: usize, y: usize },
+    SetI(u16),
+    AddI(usize),
+    ReadDelay(usize),
+    SetDelay(usize),
+    SetSound(usize),
+    Jump(u16),
+    SkipEqByte { x: usize, byte: u8 },
+    SkipNeByte { x: usize, byte: u8 },
+    SkipEq { x: usize, y: usize },
+    SkipNe { x: usize, y: usize },
+    Interpret(Opcode)
+}
+
+impl MicroOp {
+    fn compile(opcode: Opcode) -> MicroOp {
+        match opcode {
+            Opcode::LDVxByte{x, byte} => MicroOp::SetByte { x: x as usize, byte },
+            Opcode::ADDVxByte{x, byte} => MicroOp::AddByte { x: x as usize, byte },
+            Opcode::LDVxVy{x, y} => MicroOp::Copy { x: x as usize, y: y as usize },
+            Opcode::ADDVxVy{x, y} => MicroOp::Add { x: x as usize, y: y as usize },
+            Opcode::SUBVxVy{x, y} => MicroOp::Sub { x: x as usize, y: y as usize },
+            Opcode::SUBNVxVy{x, y} => MicroOp::SubN { x: x as usize, y: y as usize },
+            Opcode::LDIAddr{addr} => MicroOp::SetI(addr),
+            Opcode::ADDIVx{x} => MicroOp::AddI(x as usize),
+            Opcode::LDVxDT{x} => MicroOp::ReadDelay(x as usize),
+            Opcode::LDDTVx{x} => MicroOp::SetDelay(x as usize),
+            Opcode::LDSTVx{x} => MicroOp::SetSound(x as usize),
+            Opcode::JP{addr} => MicroOp::Jump(addr),
+            Opcode::SEVxByte{x, byte} => MicroOp::SkipEqByte { x: x as usize, byte },
+            Opcode::SNEVxByte{x, byte} => MicroOp::SkipNeByte { x: x as usize, byte },
+            Opcode::SEVxVy{x, y} => MicroOp::SkipEq { x: x as usize, y: y as usize },
+            Opcode::SNEVxVy{x, y} => MicroOp::SkipNe { x: x as usize, y: y as usize },
+            opcode => MicroOp::Interpret(opcode)
+        }
+    }
+
+    #[inline]
+    fn execute(&self, state: &mut ProcState) -> Result<(), Fault> {
+        let v = &mut state.vreg;
+        match *self {
+            MicroOp::SetByte{x, byte} => v[x] = byte,
+            MicroOp::AddByte{x, byte} => v[x] = v[x].wrapping_add(byte),
+            MicroOp::Copy{x, y} => v[x] = v[y],
+            MicroOp::Add{x, y} => {
+                let (val, overflowed) = v[x].overflowing_add(v[y]);
+                v[x] = val;
+                v[0xF] = overflowed as u8;
+            },
+            MicroOp::Sub{x, y} => {
+                let (val, borrowed) = v[x].overflowing_sub(v[y]);
+                v[x] = val;
+                v[0xF] = !borrowed as u8;
+            },
+            MicroOp::SubN{x, y} => {
+                let (val, borrowed) = v[y].overflowing_sub(v[x]);
+                v[x] = val;
+                v[0xF] = !borrowed as u8;
+            },
+            MicroOp::SetI(addr) => state.ireg = addr,
+            MicroOp::AddI(x) => state.ireg = state.ireg.wrapping_add(v[x] as u16),
+            MicroOp::ReadDelay(x) => v[x] = state.delay_t,
+            MicroOp::SetDelay(x) => state.delay_t = v[x],
+            MicroOp::SetSound(x) => state.sound_t = v[x],
+            MicroOp::Jump(addr) => state.pc = addr,
+            MicroOp::SkipEqByte{x, byte} => state.pc = state.pc.wrapping_add(2 * (v[x] == byte) as u16),
+            MicroOp::SkipNeByte{x, byte} => state.pc = state.pc.wrapping_add(2 * (v[x] != byte) as u16),
+            MicroOp::SkipEq{x, y} => state.pc = state.pc.wrapping_add(2 * (v[x] == v[y]) as u16),
+            MicroOp::SkipNe{x, y} => state.pc = state.pc.wrapping_add(2 * (v[x] != v[y]) as u16),
+            MicroOp::Interpret(opcode) => return state.try_execute_opcode(opcode)
+        }
+        Ok(())
+    }
+}
+
+/// A straight run of instructions, ending at the first one that may leave the run: a jump,
+/// call, return or skip, anything that waits, and anything that writes memory.
+struct Block {
+    start: u16,
+    ops: Vec<(Opcode, MicroOp)>,
+    /// Where the block always continues, if that is known when compiling it.
+    next: Option<u16>,
+    /// How many bytes the last instruction writes at I, if it writes memory.
+    writes: Option<u16>
+}
+
+/// Runs a machine by compiling the code it reaches into blocks and running those, giving the
+/// same results as the interpreter. Blocks are compiled on first use and thrown away when
+/// memory under them is written; code that has been written to is interpreted from then on,
+/// as are `JP V0` targets outside memory and instructions that straddle its end.
+///
+/// Anything writing to `mem` other than the machine itself must call `invalidate` afterwards.
+pub struct Recompiler {
+    blocks: Vec<Option<Box<Block>>>,
+    /// The start of every block covering each address.
+    covered_by: Vec<Vec<u16>>,
+    /// Addresses written since the recompiler started.
+    modified: Vec<bool>
+}
+
+impl Default for Recompiler {
+    fn default() -> Self {
+        Recompiler::new()
+    }
+}
+
+impl Recompiler {
+    pub fn new() -> Recompiler {
+        Recompiler {
+            blocks: (0 .. MAX_MEMORY_SIZE).map(|_| None).collect(),
+            covered_by: vec![Vec::new(); MAX_MEMORY_SIZE],
+            modified: vec![false; MAX_MEMORY_SIZE]
+        }
+    }
+
+    /// Throws away every compiled block.
+    pub fn invalidate(&mut self) {
+        *self = Recompiler::new();
+    }
+
+    /// Executes up to `budget` instructions, calling `on_step` with the state after each.
+    pub fn run<F: FnMut(&ProcState, Opcode)>(&mut self, state: &mut ProcState, budget: u32, mut on_step: F) -> Result<(), Fault> {
+        let mut remaining = budget as usize;
+
+        while remaining > 0 {
+            if !self.compile_at(state) {
+                self.interpret(state, &mut on_step)?;
+                remaining -= 1;
+                continue;
+            }
+
+            let mut block = self.blocks[state.pc as usize].as_deref().unwrap();
+            let mut write = None;
+            loop {
+                let count = block.ops.len().min(remaining);
+                let mut pc = block.start;
+                for (i, (opcode, op)) in block.ops[.. count].iter().enumerate() {
+                    pc += 2;
+                    state.pc = pc;
+                    // Only the last instruction of a block can write memory
+                    if i + 1 == block.ops.len() {
+                        write = block.writes.map(|len| (state.ireg, len));
+                    }
+                    op.execute(state)?;
+                    on_step(state, *opcode);
+                }
+                remaining -= count;
+
+                // Chain straight into the next block while it is still valid
+                let chained = block.next
+                    .filter(|next| *next == state.pc && count == block.ops.len() && write.is_none() && remaining > 0)
+                    .and_then(|next| self.blocks[next as usize].as_deref());
+                match chained {
+                    Some(next) => block = next,
+                    None => break
+                }
+            }
+
+            if let Some(write) = write {
+                self.written(write);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the block starting at the program counter unless it already is. `false` if the
+    /// instruction there has to be interpreted.
+    fn compile_at(&mut self, state: &ProcState) -> bool {
+        let pc = state.pc as usize;
+        if let Some(Some(_)) = self.blocks.get(pc) {
+            return true;
+        }
+        if pc + 1 >= MAX_MEMORY_SIZE || self.is_modified(pc) {
+            return false;
+        }
+
+        let block = self.compile(state, pc as u16);
+        for addr in pc .. pc + block.ops.len() * 2 {
+            self.covered_by[addr].push(pc as u16);
+        }
+        self.blocks[pc] = Some(Box::new(block));
+        true
+    }
+
+    fn compile(&self, state: &ProcState, start: u16) -> Block {
+        let mut ops = Vec::new();
+        let mut addr = start as usize;
+
+        loop {
+            let opcode = get_opcode((state.mem[addr] as u16) << 8 | state.mem[addr + 1] as u16);
+            ops.push((opcode, MicroOp::compile(opcode)));
+            addr += 2;
+
+            let writes = opcode.memory().filter(|access| access.write).map(|access| access.len);
+            let ends_block = opcode.changes_flow() || writes.is_some() || matches!(opcode, Opcode::DRW{..});
+            if ends_block {
+                let next = match opcode.flow() {
+                    Flow::Jump(addr) | Flow::Call(addr) => Some(addr),
+                    Flow::Next => Some(addr as u16),
+                    _ => None
+                };
+                return Block { start, ops, next, writes };
+            }
+            if ops.len() == MAX_BLOCK_LEN || addr + 1 >= MAX_MEMORY_SIZE || self.is_modified(addr) {
+                return Block { start, ops, next: Some(addr as u16), writes: None };
+            }
+        }
+    }
+
+    fn interpret<F: FnMut(&ProcState, Opcode)>(&mut self, state: &mut ProcState, on_step: &mut F) -> Result<(), Fault> {
+        let opcode = state.fetch_and_decode_opcode();
+        let write = self.write_range(state, &opcode);
+        state.try_execute_opcode(opcode)?;
+        if let Some(write) = write {
+            self.written(write);
+        }
+        on_step(state, opcode);
+        Ok(())
+    }
+
+    /// Whether the instruction at `addr` has been written to.
+    fn is_modified(&self, addr: usize) -> bool {
+        self.modified[addr] || self.modified[(addr + 1) % MAX_MEMORY_SIZE]
+    }
+
+    /// The memory an instruction is about to write, as a start address and length.
+    fn write_range(&self, state: &ProcState, opcode: &Opcode) -> Option<(u16, u16)> {
+        opcode.memory()
+            .filter(|access| access.write)
+            .map(|access| (state.ireg, access.len))
+    }
+
+    fn written(&mut self, (start, len): (u16, u16)) {
+        for offset in 0 .. len {
+            let addr = start.wrapping_add(offset) as usize % MAX_MEMORY_SIZE;
+            self.modified[addr] = true;
+            for block in self.covered_by[addr].drain(..) {
+                self.blocks[block as usize] = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::cpu::STARTING_PROGRAM_COUNTER;
+    use crate::quirks::Platform;
+
+    use super::*;
+
+    fn state(program: &[u8]) -> ProcState {
+        let mut mem = [0x0; MAX_MEMORY_SIZE];
+        mem[0x200 .. 0x200 + program.len()].copy_from_slice(program);
+        let mut state = ProcState::new(mem, Rc::new(Cell::new(None)));
+        state.seed(0);
+        state
+    }
+
+    /// Runs a program through the interpreter and through the recompiler `chunk` instructions
+    /// at a time, with a timer tick after each chunk, and checks they agree after every chunk.
+    fn compare(program: &[u8], platform: Platform, chunks: u32, chunk: u32) {
+        let mut interpreted = state(program);
+        let mut recompiled = state(program);
+        interpreted.quirks = platform.quirks();
+        recompiled.quirks = platform.quirks();
+        let mut recompiler = Recompiler::new();
+
+        for n in 0 .. chunks {
+            let mut expected = Vec::new();
+            let mut fault = None;
+            for _ in 0 .. chunk {
+                let opcode = interpreted.fetch_and_decode_opcode();
+                if let Err(e) = interpreted.try_execute_opcode(opcode) {
+                    fault = Some(e);
+                    break;
+                }
+                expected.push(opcode);
+            }
+
+            let mut actual = Vec::new();
+            let result = recompiler.run(&mut recompiled, chunk, |_, opcode| actual.push(opcode));
+
+            assert_eq!((actual, result.err()), (expected, fault), "chunk {}", n);
+            assert_eq!((recompiled.pc, recompiled.ireg, recompiled.vreg), (interpreted.pc, interpreted.ireg, interpreted.vreg), "chunk {}", n);
+            assert_eq!((recompiled.sp, recompiled.stack), (interpreted.sp, interpreted.stack), "chunk {}", n);
+            assert_eq!(recompiled.video_buffer, interpreted.video_buffer, "chunk {}", n);
+            assert_eq!(&recompiled.mem[..], &interpreted.mem[..], "chunk {}", n);
+            if fault.is_some() {
+                return;
+            }
+            interpreted.clock_tick(1);
+            recompiled.clock_tick(1);
+        }
+    }
+
+    #[test]
+    pub fn blocks_end_at_control_flow_and_memory_writes() {
+        // LD V0, 1; ADD V0, 2; SE V0, 3; LD I, 0x300; LD [I], V0; JP 0x200
+        let state = state(&[0x60, 0x01, 0x70, 0x02, 0x30, 0x03, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]);
+        let recompiler = Recompiler::new();
+
+        let first = recompiler.compile(&state, 0x200);
+        assert_eq!(first.ops.len(), 3);
+        assert_eq!(first.next, None);
+
+        let second = recompiler.compile(&state, 0x206);
+        assert_eq!(second.ops.len(), 2);
+        assert_eq!(second.next, Some(0x20A));
+
+        assert_eq!(recompiler.compile(&state, 0x20A).next, Some(0x200));
+    }
+
+    #[test]
+    pub fn budget_stops_in_the_middle_of_a_block() {
+        let mut state = state(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x00]);
+        let mut recompiler = Recompiler::new();
+
+        recompiler.run(&mut state, 2, |_, _| ()).unwrap();
+        assert_eq!((state.pc, state.vreg[0x1], state.vreg[0x2]), (0x204, 0x2, 0x0));
+
+        // On from the middle of the block, through the jump and round the loop again
+        recompiler.run(&mut state, 6, |_, _| ()).unwrap();
+        assert_eq!(state.pc, 0x200);
+    }
+
+    #[test]
+    pub fn self_modifying_code_is_interpreted_after_a_write() {
+        // LD V2, 0x05; LD I, 0x200; LD V0, 0x62; ADD V1, 0x01; LD [I], V1; JP 0x200
+        let mut state = state(&[0x62, 0x05, 0xA2, 0x00, 0x60, 0x62, 0x71, 0x01, 0xF1, 0x55, 0x12, 0x00]);
+        let mut recompiler = Recompiler::new();
+
+        recompiler.run(&mut state, 12, |_, _| ()).unwrap();
+        recompiler.run(&mut state, 1, |_, _| ()).unwrap();
+
+        assert_eq!(state.vreg[0x2], 0x2);
+        assert!(recompiler.blocks[STARTING_PROGRAM_COUNTER as usize].is_none());
+        assert!(recompiler.is_modified(0x200));
+    }
+
+    #[test]
+    pub fn recompiled_programs_match_the_interpreter() {
+        // Calls, skips, BCD, indirect jumps and a subroutine that rewrites its own operand
+        let program = [
+            0x22, 0x10, // 0x200: CALL 0x210
+            0x71, 0x01, // ADD V1, 1
+            0x31, 0x05, // SE V1, 5
+            0x12, 0x00, // JP 0x200
+            0x60, 0x04, // LD V0, 4
+            0xB2, 0x10, // JP V0, 0x210
+            0x00, 0x00,
+            0x00, 0x00,
+            0xA2, 0x17, // 0x210: LD I, 0x217
+            0xF1, 0x33, // LD B, V1
+            0x62, 0x00, // LD V2, 0
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x00, 0xEE, // RET
+            0xFF, 0xFF  // 0x21A
+        ];
+
+        for platform in Platform::ALL.iter() {
+            compare(&program, *platform, 200, 1);
+            compare(&program, *platform, 20, 15);
+        }
+    }
+
+    #[test]
+    pub fn random_programs_match_the_interpreter() {
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        for _ in 0 .. 200 {
+            let program: Vec<u8> = (0 .. 64).map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                // Plenty of jumps and calls back into the program
+                match (seed >> 32) as u8 % 8 {
+                    0 => 0x12,
+                    1 => 0x22,
+                    _ => seed as u8
+                }
+            }).collect();
+
+            for platform in Platform::ALL.iter() {
+                compare(&program, *platform, 100, 1);
+                compare(&program, *platform, 10, 12);
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Rgb;
+use crate::display::Filters;
+use crate::frontend::Scaling;
+use crate::keymap::Keymap;
+
+/// `$XDG_CONFIG_HOME/chip8`, or `~/.config/chip8` if that is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("chip8"))
+}
+
+/// Options that can be saved rather than given every time. Anything left out falls back to a
+/// less specific source.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Instructions per 60Hz frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipf: Option<u32>,
+    /// Background then foreground, as `#rrggbb`.
+    #[serde(with = "palette", skip_serializing_if = "Option::is_none")]
+    pub palette: Option<(Rgb, Rgb)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    #[serde(with = "scaling", skip_serializing_if = "Option::is_none")]
+    pub scaling: Option<Scaling>,
+    /// Flicker filters, as a list like `blend=3,phosphor=0.7,vblank`.
+    #[serde(with = "filters", skip_serializing_if = "Option::is_none")]
+    pub filters: Option<Filters>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fullscreen: Option<bool>,
+    /// Waits for the display's refresh before showing each frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vsync: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
+    /// The id of a bundled font or the path of a raw font file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    /// Where the font is loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_address: Option<u16>,
+    /// Key bindings, either a layout like `1234AZERQSDFWXCV` or a table of inputs to keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Keymap>
+}
+
+impl Settings {
+    /// These settings with any set in `over` replacing them. Key bindings are combined.
+    pub fn merge(&self, over: &Settings) -> Settings {
+        let keys = match (&self.keys, &over.keys) {
+            (Some(keys), Some(over)) => {
+                let mut keys = keys.clone();
+                keys.extend(over);
+                Some(keys)
+            },
+            (keys, over) => over.clone().or_else(|| keys.clone())
+        };
+
+        Settings {
+            ipf: over.ipf.or(self.ipf),
+            palette: over.palette.or(self.palette),
+            scale: over.scale.or(self.scale),
+            scaling: over.scaling.or(self.scaling),
+            filters: over.filters.or(self.filters),
+            fullscreen: over.fullscreen.or(self.fullscreen),
+            vsync: over.vsync.or(self.vsync),
+            mute: over.mute.or(self.mute),
+            font: over.font.clone().or_else(|| self.font.clone()),
+            font_address: over.font_address.or(self.font_address),
+            keys
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Write(toml::ser::Error)
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to access config: {}", e),
+            ConfigError::Parse(e) => write!(f, "malformed config: {}", e),
+            ConfigError::Write(e) => write!(f, "cannot write config: {}", e)
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(e: toml::ser::Error) -> Self {
+        ConfigError::Write(e)
+    }
+}
+
+/// The config file: global settings at the top level and `[roms."<sha1 or file name>"]` tables
+/// that override them for one ROM.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Config {
+    #[serde(flatten)]
+    pub global: Settings,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roms: BTreeMap<String, Settings>
+}
+
+impl TryFrom<toml::Table> for Config {
+    type Error = toml::de::Error;
+
+    /// Reads the global settings apart from `roms`, since flattening them into `Config` would
+    /// let unknown keys at the top level through.
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let roms = match table.remove("roms") {
+            Some(roms) => roms.try_into()?,
+            None => BTreeMap::new()
+        };
+        Ok(Config { global: toml::Value::Table(table).try_into()?, roms })
+    }
+}
+
+impl Config {
+    /// `config.toml` in `config_dir()`.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Reads the config file, treating a missing one as empty.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(toml::from_str(&text)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The overrides for a ROM: those for its file name, then those for its SHA-1 on top.
+    pub fn rom_settings(&self, sha1: &str, file_name: Option<&str>) -> Settings {
+        file_name.into_iter()
+            .chain(Some(sha1))
+            .filter_map(|rom| self.roms.get(rom))
+            .fold(Settings::default(), |settings, rom| settings.merge(rom))
+    }
+
+    /// The settings for a ROM, with its overrides applied to the global ones.
+    pub fn settings_for(&self, sha1: &str, file_name: Option<&str>) -> Settings {
+        self.global.merge(&self.rom_settings(sha1, file_name))
+    }
+
+    /// The keymap for a ROM: the global bindings (or `fallback` if there are none) with `extra`
+    /// on top, then the ROM's own bindings.
+    pub fn keymap_for(&self, fallback: &Keymap, extra: &Keymap, sha1: &str, file_name: Option<&str>) -> Keymap {
+        let mut keymap = self.global.keys.clone().unwrap_or_else(|| fallback.clone());
+        keymap.extend(extra);
+        if let Some(keys) = self.rom_settings(sha1, file_name).keys {
+            keymap.extend(&keys);
+        }
+        keymap
+    }
+}
+
+mod palette {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::color::Rgb;
+
+    pub fn serialize<S: Serializer>(palette: &Option<(Rgb, Rgb)>, serializer: S) -> Result<S::Ok, S::Error> {
+        match palette {
+            Some((background, foreground)) => {
+                serializer.collect_seq(&[background.to_string(), foreground.to_string()])
+            },
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(Rgb, Rgb)>, D::Error> {
+        let (background, foreground) = <(String, String)>::deserialize(deserializer)?;
+        Ok(Some((background.parse().map_err(D::Error::custom)?, foreground.parse().map_err(D::Error::custom)?)))
+    }
+}
+
+mod scaling {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::frontend::Scaling;
+
+    pub fn serialize<S: Serializer>(scaling: &Option<Scaling>, serializer: S) -> Result<S::Ok, S::Error> {
+        match scaling {
+            Some(scaling) => serializer.collect_str(scaling),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Scaling>, D::Error> {
+        String::deserialize(deserializer)?.parse().map(Some).map_err(D::Error::custom)
+    }
+}
+
+mod filters {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::display::Filters;
+
+    pub fn serialize<S: Serializer>(filters: &Option<Filters>, serializer: S) -> Result<S::Ok, S::Error> {
+        match filters {
+            Some(filters) => serializer.collect_str(filters),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Filters>, D::Error> {
+        String::deserialize(deserializer)?.parse().map(Some).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn rom_sections_override_global_settings() {
+        let config: Config = toml::from_str(r##"
+            ipf = 15
+            palette = ["#000000", "#ffffff"]
+            mute = true
+            vsync = false
+            keys = { Space = 5 }
+
+            [roms."pong.ch8"]
+            ipf = 30
+            scaling = "stretch"
+            filters = "blend=2,vblank"
+            font = "dream6800"
+            font_address = 0x50
+
+            [roms.abc]
+            palette = ["#000000", "#33ff66"]
+            keys = { Return = 8 }
+        "##).unwrap();
+
+        let settings = config.settings_for("abc", Some("pong.ch8"));
+        assert_eq!(settings.ipf, Some(30));
+        assert_eq!(settings.palette, Some((Rgb::BLACK, "#33ff66".parse().unwrap())));
+        assert_eq!(settings.scaling, Some(Scaling::Stretch));
+        assert_eq!(settings.filters, Some("blend=2,vblank".parse().unwrap()));
+        assert_eq!((settings.mute, settings.vsync), (Some(true), Some(false)));
+        assert_eq!(settings.scale, None);
+        assert_eq!((settings.font.as_deref(), settings.font_address), (Some("dream6800"), Some(0x50)));
+
+        let keys = settings.keys.unwrap();
+        assert_eq!((keys.get("space"), keys.get("return")), (Some(0x5), Some(0x8)));
+
+        assert_eq!(config.settings_for("def", None).ipf, Some(15));
+    }
+
+    #[test]
+    pub fn malformed_settings_are_rejected() {
+        assert!(toml::from_str::<Config>(r#"palette = ["black", "white"]"#).is_err());
+        assert!(toml::from_str::<Config>(r#"scaling = "zoom""#).is_err());
+        assert!(toml::from_str::<Config>(r#"filters = "sharpen""#).is_err());
+        assert!(toml::from_str::<Config>(r#"[roms.abc]
+            speed = 12"#).is_err());
+        assert!(toml::from_str::<Config>("speed = 12\nipf = 3").is_err());
+        assert!(toml::from_str::<Config>("keys = { Q = 16 }").is_err());
+        assert!(toml::from_str::<Config>(r#"keys = "1234""#).is_err());
+    }
+
+    #[test]
+    pub fn config_round_trips_through_a_file() {
+        let path = env::temp_dir().join(format!("chip8-config-test-{}", std::process::id())).join("config.toml");
+        let mut config = Config::default();
+        config.global.scale = Some(8);
+        config.global.palette = Some((Rgb::BLACK, Rgb::WHITE));
+        config.global.filters = Some("phosphor=0.5,vblank".parse().unwrap());
+        config.roms.insert("abc".to_string(), Settings { ipf: Some(20), keys: Some(Keymap::qwerty()), ..Settings::default() });
+
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded, config);
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+    }
+
+    #[test]
+    pub fn keymaps_layer_rom_bindings_over_global_ones() {
+        let config: Config = toml::from_str(r#"
+            keys = "1234azerqsdfwxcv"
+
+            [roms."pong.ch8"]
+            keys = { Up = 1, Space = 6, "pad:dpdown" = 4 }
+        "#).unwrap();
+
+        let mut extra = Keymap::default();
+        extra.bind("Space", 0x5);
+        extra.bind("Return", 0x8);
+
+        let keymap = config.keymap_for(&Keymap::qwerty(), &extra, "abc", Some("pong.ch8"));
+        assert_eq!(keymap.get("a"), Some(0x4));
+        assert_eq!(keymap.get("up"), Some(0x1));
+        assert_eq!(keymap.get("PAD:DPDOWN"), Some(0x4));
+        assert_eq!((keymap.get("Space"), keymap.get("Return")), (Some(0x6), Some(0x8)));
+
+        let keymap = Config::default().keymap_for(&Keymap::qwerty(), &Keymap::default(), "abc", None);
+        assert_eq!(keymap, Keymap::qwerty());
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+
+use crate::cart::{CartError, Cartridge};
+use crate::quirks::Platform;
+
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+/// Where a ROM is read from, as named on the command line:
+///
+/// * `-` reads a raw ROM from stdin
+/// * `games.zip:pong.ch8` reads the named entry of a zip archive
+/// * `games.zip` reads the only ROM in a zip archive
+/// * anything else is a file path
+///
+/// `.hex`, `.ihx` and `.txt` files are parsed as Intel HEX or hex text dumps, everything else
+/// is raw binary.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomSource {
+    Stdin,
+    File(PathBuf),
+    Zip { archive: PathBuf, entry: Option<String> }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum RomFormat {
+    Binary,
+    HexText,
+    IntelHex
+}
+
+impl RomSource {
+    pub fn parse(name: &str) -> RomSource {
+        if name == "-" {
+            return RomSource::Stdin;
+        }
+
+        let lower = name.to_ascii_lowercase();
+        if let Some(idx) = lower.rfind(".zip:") {
+            return RomSource::Zip {
+                archive: PathBuf::from(&name[.. idx + 4]),
+                entry: Some(name[idx + 5 ..].to_string())
+            };
+        }
+        if lower.ends_with(".zip") {
+            return RomSource::Zip { archive: PathBuf::from(name), entry: None };
+        }
+
+        RomSource::File(PathBuf::from(name))
+    }
+
+    /// The file name of the ROM itself, without any directory or archive prefix.
+    pub fn file_name(&self) -> Option<&str> {
+        match self {
+            RomSource::Stdin => None,
+            RomSource::File(path) => path.file_name().and_then(|f| f.to_str()),
+            RomSource::Zip{entry: Some(entry), ..} => entry.rsplit('/').next(),
+            RomSource::Zip{archive, entry: None} => archive.file_stem().and_then(|f| f.to_str())
+        }
+    }
+
+    /// The platform implied by the ROM's file extension, if any.
+    pub fn platform(&self) -> Option<Platform> {
+        self.file_name()
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|ext| ext.to_str())
+            .and_then(Platform::from_extension)
+    }
+
+    pub fn load(&self) -> Result<Cartridge, CartError> {
+        match self {
+            RomSource::Stdin => Cartridge::load(&mut io::stdin().lock()),
+            RomSource::File(path) => read_rom(&mut File::open(path)?, format_of(path)),
+            RomSource::Zip{archive, entry} => {
+                let mut zip = ZipArchive::new(File::open(archive)?)
+                    .map_err(|e| CartError::Archive(e.to_string()))?;
+
+                let name = match entry {
+                    Some(entry) => entry.clone(),
+                    None => only_rom_in(&mut zip)?
+                };
+                let mut file = zip.by_name(&name)
+                    .map_err(|e| CartError::Archive(format!("{}: {}", name, e)))?;
+                read_rom(&mut file, format_of(Path::new(&name)))
+            }
+        }
+    }
+}
+
+impl Display for RomSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RomSource::Stdin => write!(f, "<stdin>"),
+            RomSource::File(path) => write!(f, "{}", path.display()),
+            RomSource::Zip{archive, entry: Some(entry)} => write!(f, "{}:{}", archive.display(), entry),
+            RomSource::Zip{archive, entry: None} => write!(f, "{}", archive.display())
+        }
+    }
+}
+
+fn format_of(path: &Path) -> RomFormat {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()) {
+        Some(ext) if ext == "hex" || ext == "ihx" => RomFormat::IntelHex,
+        Some(ext) if ext == "txt" => RomFormat::HexText,
+        _ => RomFormat::Binary
+    }
+}
+
+fn read_rom(reader: &mut dyn Read, format: RomFormat) -> Result<Cartridge, CartError> {
+    if format == RomFormat::Binary {
+        return Cartridge::load(reader);
+    }
+
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    // Plenty of ".hex" files are plain hex dumps rather than Intel HEX
+    if format == RomFormat::IntelHex && text.trim_start().starts_with(':') {
+        Cartridge::from_intel_hex(&text)
+    } else {
+        Cartridge::from_hex_text(&text)
+    }
+}
+
+fn only_rom_in<R: Read + io::Seek>(zip: &mut ZipArchive<R>) -> Result<String, CartError> {
+    let names: Vec<String> = zip.file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(String::from)
+        .collect();
+    let roms: Vec<&String> = names.iter()
+        .filter(|name| {
+            let ext = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("");
+            ROM_EXTENSIONS.iter().any(|rom| rom.eq_ignore_ascii_case(ext))
+        })
+        .collect();
+
+    match (roms.as_slice(), names.as_slice()) {
+        ([rom], _) => Ok((*rom).clone()),
+        ([], [only]) => Ok(only.clone()),
+        ([], []) => Err(CartError::Archive("archive is empty".to_string())),
+        _ => {
+            let mut listed: Vec<&String> = if roms.is_empty() { names.iter().collect() } else { roms };
+            listed.sort();
+            let listed: Vec<&str> = listed.iter().map(|s| s.as_str()).collect();
+            Err(CartError::Archive(format!("choose one of: {}", listed.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parses_source_names() {
+        assert_eq!(RomSource::parse("-"), RomSource::Stdin);
+        assert_eq!(RomSource::parse("roms/pong.ch8"), RomSource::File(PathBuf::from("roms/pong.ch8")));
+        assert_eq!(RomSource::parse("games.ZIP"), RomSource::Zip { archive: PathBuf::from("games.ZIP"), entry: None });
+        assert_eq!(RomSource::parse("games.zip:chip8/pong.sc8"), RomSource::Zip {
+            archive: PathBuf::from("games.zip"),
+            entry: Some("chip8/pong.sc8".to_string())
+        });
+    }
+
+    #[test]
+    pub fn platform_follows_rom_extension() {
+        assert_eq!(RomSource::parse("pong.ch8").platform(), None);
+        assert_eq!(RomSource::parse("games.zip:chip8/pong.sc8").platform(), Some(Platform::SuperChip));
+        assert_eq!(RomSource::parse("pong.bin").platform(), None);
+        assert_eq!(RomSource::Stdin.platform(), None);
+    }
+
+    #[test]
+    pub fn text_formats_are_chosen_by_extension() {
+        assert_eq!(format_of(Path::new("pong.hex")), RomFormat::IntelHex);
+        assert_eq!(format_of(Path::new("pong.TXT")), RomFormat::HexText);
+        assert_eq!(format_of(Path::new("pong.ch8")), RomFormat::Binary);
+    }
+
+    #[test]
+    pub fn zip_entries_are_found_by_name_or_extension() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let path = std::env::temp_dir().join(format!("chip8-source-test-{}.zip", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("README", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"not a rom").unwrap();
+        zip.start_file("games/pong.ch8", SimpleFileOptions::default()).unwrap();
+        zip.write_all(&[0x00, 0xE0]).unwrap();
+        zip.finish().unwrap();
+
+        let archive = path.to_str().unwrap();
+        let by_extension = RomSource::parse(archive).load().unwrap();
+        let by_name = RomSource::parse(&format!("{}:README", archive)).load().unwrap();
+        let missing = RomSource::parse(&format!("{}:nope.ch8", archive)).load();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&by_extension.buffer[.. by_extension.size], &[0x00, 0xE0]);
+        assert_eq!(&by_name.buffer[.. by_name.size], b"not a rom");
+        assert!(matches!(missing, Err(CartError::Archive(_))));
+    }
+
+    #[test]
+    pub fn hex_files_without_records_are_read_as_hex_text() {
+        let cart = read_rom(&mut &b"00E0 1200"[..], RomFormat::IntelHex).unwrap();
+
+        assert_eq!(&cart.buffer[.. cart.size], &[0x00, 0xE0, 0x12, 0x00]);
+    }
+}
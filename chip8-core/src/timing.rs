@@ -0,0 +1,90 @@
+use crate::cpu::opcodes::Opcode;
+use crate::cpu::ProcState;
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Machine cycles between display interrupts: the VIP's 1.76 MHz clock, 8 clocks per machine
+/// cycle, 60 frames a second.
+pub const CYCLES_PER_FRAME: u64 = 3668;
+
+/// Machine cycles of each frame the interpreter loses to the display: 1024 of DMA for 128 scan
+/// lines of 8 bytes, and about 46 in the interrupt routine, which also counts the timers down.
+pub const INTERRUPT_CYCLES: u64 = 1070;
+
+/// Fetching and decoding `DRW`, and working out where the sprite goes on the screen.
+const DRW_SETUP: u64 = 48;
+/// Loading each sprite row and moving on to the next screen line.
+const DRW_ROW: u64 = 16;
+/// Drawing each screen byte a row covers and checking it for a collision.
+const DRW_BYTE: u64 = 14;
+/// Each bit a row is shifted right to line it up with the screen bytes.
+const DRW_SHIFT: u64 = 12;
+
+/// Approximate machine cycles the VIP interpreter spends drawing `rows` rows of a sprite at
+/// column `x`. Sprites at a multiple of 8 fall on one screen byte per row; anywhere else each
+/// row is shifted into place bit by bit and covers two.
+pub fn draw_cycles(x: u8, rows: u8) -> u64 {
+    let shift = (x % 8) as u64;
+    let bytes = if shift == 0 { 1 } else { 2 };
+    DRW_SETUP + rows as u64 * (DRW_ROW + bytes * DRW_BYTE + shift * DRW_SHIFT)
+}
+
+/// Machine cycles an instruction takes on the VIP, given the state before it runs. `DRW`
+/// depends on the sprite's size and position; waiting for the display interrupt is not included.
+pub fn cycles(state: &ProcState, opcode: Opcode) -> u64 {
+    match opcode {
+        Opcode::DRW{x, y, nibble} => {
+            let column = state.vreg[x as usize] % SCREEN_WIDTH as u8;
+            let top = state.vreg[y as usize] as usize % SCREEN_HEIGHT;
+            // Rows past the bottom are clipped unless the wrap quirk is set
+            let rows = if state.quirks.wrap { nibble } else { nibble.min((SCREEN_HEIGHT - top) as u8) };
+            draw_cycles(column, rows)
+        },
+        opcode => opcode.vip_cycles() as u64
+    }
+}
+
+/// The cycle at which the display interrupt after `clock` happens.
+pub fn next_interrupt(clock: u64) -> u64 {
+    (clock / CYCLES_PER_FRAME + 1) * CYCLES_PER_FRAME
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::cpu::MAX_MEMORY_SIZE;
+
+    use super::*;
+
+    #[test]
+    pub fn unaligned_and_taller_sprites_take_longer() {
+        assert!(draw_cycles(0, 5) < draw_cycles(8, 6));
+        assert!(draw_cycles(8, 5) < draw_cycles(9, 5));
+        assert!(draw_cycles(9, 5) < draw_cycles(15, 5));
+        assert_eq!(draw_cycles(16, 5), draw_cycles(0, 5));
+    }
+
+    #[test]
+    pub fn draw_cycles_follow_the_sprite_position() {
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(None)));
+        state.vreg[0x0] = 66;
+        state.vreg[0x1] = 30;
+        let drw = Opcode::DRW { x: 0x0, y: 0x1, nibble: 5 };
+
+        // Column 66 wraps to 2, and only 2 rows fit above the bottom
+        assert_eq!(cycles(&state, drw), draw_cycles(2, 2));
+
+        state.quirks.wrap = true;
+        assert_eq!(cycles(&state, drw), draw_cycles(2, 5));
+
+        assert_eq!(cycles(&state, Opcode::JP { addr: 0x200 }), 23);
+    }
+
+    #[test]
+    pub fn interrupts_fall_on_frame_boundaries() {
+        assert_eq!(next_interrupt(0), CYCLES_PER_FRAME);
+        assert_eq!(next_interrupt(CYCLES_PER_FRAME - 1), CYCLES_PER_FRAME);
+        assert_eq!(next_interrupt(CYCLES_PER_FRAME), 2 * CYCLES_PER_FRAME);
+    }
+}
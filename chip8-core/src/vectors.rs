@@ -0,0 +1,296 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::opcodes::get_opcode;
+use crate::cpu::{ProcState, MAX_MEMORY_SIZE, MAX_STACK_SIZE};
+use crate::quirks::{Platform, Quirks};
+use crate::recompiler::Recompiler;
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// The quirk profile used when a machine has no platform.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Every quirk profile a vector can be run under: `default` and the platform ids.
+pub fn profiles() -> Vec<&'static str> {
+    Some(DEFAULT_PROFILE).into_iter().chain(Platform::ALL.iter().map(|p| p.id())).collect()
+}
+
+pub fn profile_quirks(profile: &str) -> Result<Quirks, String> {
+    match profile {
+        DEFAULT_PROFILE => Ok(Quirks::default()),
+        platform => platform.parse::<Platform>().map(|p| p.quirks())
+    }
+}
+
+/// A single instruction executed from a known state, and the state it should leave behind.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TestVector {
+    pub name: String,
+    /// The raw instruction, e.g. `0x8014` for `ADD V0, V1`.
+    pub opcode: u16,
+    /// The quirk profiles under which `expected` holds.
+    pub profiles: Vec<String>,
+    pub initial: Snapshot,
+    pub expected: Snapshot
+}
+
+/// The parts of the machine a vector sets up or checks. Memory and screen rows that are not
+/// listed are zero.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    /// Return addresses, oldest first.
+    pub stack: Vec<u16>,
+    pub dt: u8,
+    pub st: u8,
+    /// The key held down, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<u8>,
+    /// Whether a 60Hz tick has happened since the last `DRW`, for the vblank quirk.
+    #[serde(skip_serializing_if = "is_false")]
+    pub vblank: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<MemoryFragment>,
+    /// Lit rows by row number, as 64 characters of `#` and `.`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub screen: BTreeMap<usize, String>
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MemoryFragment {
+    pub address: u16,
+    pub bytes: Vec<u8>
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl Snapshot {
+    /// A machine in this state, or why the snapshot does not fit in one.
+    pub fn to_state(&self, quirks: Quirks) -> Result<ProcState, String> {
+        // Slot 0 of the stack is never used
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(format!("stack has {} entries, at most {} fit", self.stack.len(), MAX_STACK_SIZE - 1));
+        }
+        for fragment in &self.memory {
+            if fragment.address as usize + fragment.bytes.len() > MAX_MEMORY_SIZE {
+                return Err(format!("{} bytes at {:#05x} run past the end of memory", fragment.bytes.len(), fragment.address));
+            }
+        }
+        if let Some(y) = self.screen.keys().find(|y| **y >= SCREEN_HEIGHT) {
+            return Err(format!("screen row {} is off the screen", y));
+        }
+
+        let mut state = ProcState::new([0x0; MAX_MEMORY_SIZE], Rc::new(Cell::new(self.key)));
+        state.quirks = quirks;
+        state.vreg = self.v;
+        state.ireg = self.i;
+        state.pc = self.pc;
+        state.delay_t = self.dt;
+        state.sound_t = self.st;
+        state.vblank = self.vblank;
+
+        state.sp = self.stack.len();
+        for (slot, addr) in state.stack[1 ..].iter_mut().zip(&self.stack) {
+            *slot = *addr;
+        }
+        for fragment in &self.memory {
+            let start = fragment.address as usize;
+            state.mem[start .. start + fragment.bytes.len()].copy_from_slice(&fragment.bytes);
+        }
+        for (y, row) in &self.screen {
+            state.video_buffer[*y] = row.chars()
+                .take(SCREEN_WIDTH)
+                .fold(0x0, |line, c| line << 1 | if c == '#' { 0x1 } else { 0x0 });
+        }
+        Ok(state)
+    }
+
+    /// The state of a machine, with memory described by the fragments covering `ranges` and any
+    /// bytes that differ from `before`.
+    pub fn capture(state: &ProcState, before: &[u8; MAX_MEMORY_SIZE], ranges: &[(u16, usize)]) -> Snapshot {
+        let mut covered = vec![false; MAX_MEMORY_SIZE];
+        for (address, len) in ranges {
+            for byte in covered.iter_mut().skip(*address as usize).take(*len) {
+                *byte = true;
+            }
+        }
+        for (addr, (old, new)) in before.iter().zip(state.mem.iter()).enumerate() {
+            if old != new {
+                covered[addr] = true;
+            }
+        }
+
+        let mut memory: Vec<MemoryFragment> = Vec::new();
+        for (addr, _) in covered.iter().enumerate().filter(|(_, covered)| **covered) {
+            match memory.last_mut() {
+                Some(fragment) if fragment.address as usize + fragment.bytes.len() == addr => {
+                    fragment.bytes.push(state.mem[addr]);
+                },
+                _ => memory.push(MemoryFragment { address: addr as u16, bytes: vec![state.mem[addr]] })
+            }
+        }
+
+        let screen = state.video_buffer.iter()
+            .enumerate()
+            .filter(|(_, line)| **line != 0x0)
+            .map(|(y, line)| {
+                let row = (0 .. SCREEN_WIDTH)
+                    .map(|x| if (line >> (SCREEN_WIDTH - 1 - x)) & 0x1 == 0x1 { '#' } else { '.' })
+                    .collect();
+                (y, row)
+            })
+            .collect();
+
+        Snapshot {
+            v: state.vreg,
+            i: state.ireg,
+            pc: state.pc,
+            stack: state.stack[1 ..= state.sp].to_vec(),
+            dt: state.delay_t,
+            st: state.sound_t,
+            key: state.io_queue.held(),
+            vblank: state.vblank,
+            memory,
+            screen
+        }
+    }
+}
+
+impl TestVector {
+    /// Executes the instruction from the initial state under a quirk profile.
+    pub fn run(&self, quirks: Quirks) -> Result<Snapshot, String> {
+        let mut state = self.initial_state(quirks)?;
+        let before = state.mem;
+
+        state.execute_opcode(get_opcode(self.opcode));
+        Ok(self.capture(&state, &before))
+    }
+
+    /// Like `run`, but fetches the instruction from just before the program counter and runs
+    /// it through the `Recompiler`.
+    pub fn run_recompiled(&self, quirks: Quirks) -> Result<Snapshot, String> {
+        let mut state = self.initial_state(quirks)?;
+        state.pc = state.pc.wrapping_sub(2);
+        let addr = state.pc as usize % MAX_MEMORY_SIZE;
+        state.mem[addr] = (self.opcode >> 8) as u8;
+        state.mem[(addr + 1) % MAX_MEMORY_SIZE] = self.opcode as u8;
+        let before = state.mem;
+
+        Recompiler::new().run(&mut state, 1, |_, _| ()).unwrap_or_else(|fault| panic!("{}: {}", fault, &state));
+        Ok(self.capture(&state, &before))
+    }
+
+    fn initial_state(&self, quirks: Quirks) -> Result<ProcState, String> {
+        self.initial.to_state(quirks).map_err(|e| format!("{}: initial state: {}", self.name, e))
+    }
+
+    fn capture(&self, state: &ProcState, before: &[u8; MAX_MEMORY_SIZE]) -> Snapshot {
+        let ranges: Vec<(u16, usize)> = self.initial.memory.iter()
+            .chain(self.expected.memory.iter())
+            .map(|fragment| (fragment.address, fragment.bytes.len()))
+            .collect();
+        Snapshot::capture(state, before, &ranges)
+    }
+
+    /// Where the state after running under `profile` differs from `expected`, one line per
+    /// field.
+    pub fn check(&self, profile: &str) -> Result<(), String> {
+        self.compare(&self.run(profile_quirks(profile)?)?)
+    }
+
+    /// Like `check`, running the instruction with `run_recompiled`.
+    pub fn check_recompiled(&self, profile: &str) -> Result<(), String> {
+        self.compare(&self.run_recompiled(profile_quirks(profile)?)?)
+    }
+
+    fn compare(&self, actual: &Snapshot) -> Result<(), String> {
+        let expected = &self.expected;
+
+        let mut errors = Vec::new();
+        let mut compare = |field: &str, expected: String, actual: String| {
+            if expected != actual {
+                errors.push(format!("{}: expected {}, got {}", field, expected, actual));
+            }
+        };
+        compare("V", format!("{:02x?}", expected.v), format!("{:02x?}", actual.v));
+        compare("I", format!("{:#05x}", expected.i), format!("{:#05x}", actual.i));
+        compare("PC", format!("{:#05x}", expected.pc), format!("{:#05x}", actual.pc));
+        compare("stack", format!("{:03x?}", expected.stack), format!("{:03x?}", actual.stack));
+        compare("DT", expected.dt.to_string(), actual.dt.to_string());
+        compare("ST", expected.st.to_string(), actual.st.to_string());
+        compare("vblank", expected.vblank.to_string(), actual.vblank.to_string());
+        compare("memory", format!("{:02x?}", expected.memory), format!("{:02x?}", actual.memory));
+        for y in 0 .. SCREEN_HEIGHT {
+            let blank = ".".repeat(SCREEN_WIDTH);
+            let row = |snapshot: &Snapshot| snapshot.screen.get(&y).cloned().unwrap_or_else(|| blank.clone());
+            compare(&format!("screen row {}", y), row(expected), row(actual));
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn snapshots_round_trip_through_a_machine() {
+        let mut snapshot = Snapshot { i: 0x300, pc: 0x202, stack: vec![0x204, 0x310], dt: 3, key: Some(0xA), ..Snapshot::default() };
+        snapshot.v[0xF] = 1;
+        snapshot.memory.push(MemoryFragment { address: 0x300, bytes: vec![0xF0, 0x90] });
+        snapshot.screen.insert(5, format!("#{}#", ".".repeat(62)));
+
+        let state = snapshot.to_state(Quirks::default()).unwrap();
+        let captured = Snapshot::capture(&state, &state.mem, &[(0x300, 2)]);
+
+        assert_eq!(captured, snapshot);
+    }
+
+    #[test]
+    pub fn vectors_report_the_fields_that_differ() {
+        // ADD V0, V1 with a carry
+        let mut initial = Snapshot { pc: 0x202, ..Snapshot::default() };
+        initial.v[0x0] = 0xFF;
+        initial.v[0x1] = 0x02;
+        let mut expected = initial.clone();
+        expected.v[0x0] = 0x01;
+        expected.v[0xF] = 0x01;
+        let mut vector = TestVector { name: "add".to_string(), opcode: 0x8014, profiles: vec![], initial, expected };
+
+        assert_eq!(vector.check(DEFAULT_PROFILE), Ok(()));
+
+        vector.expected.v[0xF] = 0x0;
+        vector.expected.pc = 0x204;
+        let errors = vector.check(DEFAULT_PROFILE).unwrap_err();
+        assert!(errors.starts_with("V: expected"));
+        assert!(errors.contains("PC: expected 0x204, got 0x202"));
+    }
+
+    #[test]
+    pub fn vectors_that_do_not_fit_the_machine_are_rejected() {
+        let initial = Snapshot { pc: 0x202, stack: vec![0x204; MAX_STACK_SIZE - 1], ..Snapshot::default() };
+        let mut vector = TestVector { name: "ret".to_string(), opcode: 0x00EE, profiles: vec![], initial, expected: Snapshot::default() };
+        assert!(vector.run(Quirks::default()).is_ok());
+
+        vector.initial.stack.push(0x206);
+        assert_eq!(vector.check(DEFAULT_PROFILE), Err("ret: initial state: stack has 16 entries, at most 15 fit".to_string()));
+
+        vector.initial.stack.clear();
+        vector.initial.memory.push(MemoryFragment { address: 0xFFE, bytes: vec![0x0; 3] });
+        assert_eq!(vector.run_recompiled(Quirks::default()),
+            Err("ret: initial state: 3 bytes at 0xffe run past the end of memory".to_string()));
+
+        vector.initial.memory.clear();
+        vector.initial.screen.insert(SCREEN_HEIGHT, "#".to_string());
+        assert!(vector.run(Quirks::default()).unwrap_err().contains("screen row 32"));
+    }
+}
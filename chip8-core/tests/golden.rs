@@ -0,0 +1,181 @@
+//! Boots every ROM in `roms/` without a frontend and compares the screen after a number of frames
+//! with the reference image in `tests/golden/<rom>.txt`.
+//!
+//! ROMs that need more frames, key presses or a particular platform are described in
+//! `tests/golden/roms.toml`; anything else runs for `DEFAULT_FRAMES` with the settings from the
+//! ROM database. Each ROM runs in every one of `ENGINES`, which must agree. Community test
+//! ROMs can be dropped into `roms/` and given references with
+//!
+//!     CHIP8_UPDATE_GOLDEN=1 cargo test -p chip8-core --test golden
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use chip8_core::db::RomDatabase;
+use chip8_core::frontend::Emulator;
+use chip8_core::machine::Machine;
+use chip8_core::quirks::Platform;
+use chip8_core::source::RomSource;
+use chip8_core::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const DEFAULT_FRAMES: u32 = 120;
+const DEFAULT_TICKRATE: u32 = 10;
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+/// Ways of running a ROM, which must all end up with the same screen.
+const ENGINES: [&str; 3] = ["interpreter", "decode cache", "recompiler"];
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RomOptions {
+    frames: Option<u32>,
+    tickrate: Option<u32>,
+    platform: Option<String>,
+    seed: Option<u64>,
+    /// Bytes written to memory before the ROM starts, e.g. the test selector many test ROMs
+    /// read from 0x1FF.
+    poke: Vec<Poke>,
+    /// Keys held from a frame onwards, each until the next entry.
+    keys: Vec<KeyPress>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Poke {
+    address: u16,
+    value: u8
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyPress {
+    frame: u32,
+    /// Released if left out.
+    key: Option<u8>
+}
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).to_path_buf()
+}
+
+fn roms() -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(root().join("../roms"))
+        .expect("roms directory")
+        .map(|entry| entry.expect("roms directory entry").path())
+        .filter(|path| {
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+        })
+        .collect();
+    roms.sort();
+    roms
+}
+
+/// Runs a ROM and draws its screen as text, one line per row with `#` for lit pixels.
+fn screenshot(path: &Path, options: &RomOptions, engine: &str) -> Result<String, String> {
+    let source = RomSource::parse(&path.to_string_lossy());
+    let cart = source.load().map_err(|e| e.to_string())?;
+
+    let db = RomDatabase::bundled();
+    let info = db.lookup(&cart);
+    let platform = match &options.platform {
+        Some(platform) => Some(platform.parse::<Platform>()?),
+        None => info.and_then(|i| i.platform).or_else(|| source.platform())
+    };
+
+    let mut builder = Machine::builder(&cart)
+        .platform(platform)
+        .seed(Some(options.seed.unwrap_or(0)))
+        .decode_cache(engine == "decode cache")
+        .recompiler(engine == "recompiler");
+    if let (Some(info), None) = (info, &options.platform) {
+        builder = builder.quirks(info.quirks);
+    }
+    let mut machine = builder.build().map_err(|e| e.to_string())?;
+    for poke in &options.poke {
+        machine.state.mem[poke.address as usize] = poke.value;
+    }
+    machine.invalidate_code();
+
+    let tickrate = options.tickrate
+        .or_else(|| info.and_then(|i| i.tickrate))
+        .or_else(|| platform.map(|p| p.tickrate()))
+        .unwrap_or(DEFAULT_TICKRATE);
+    let mut emulator = Emulator::new(machine, tickrate);
+
+    let mut key = None;
+    for frame in 0 .. options.frames.unwrap_or(DEFAULT_FRAMES) {
+        if let Some(press) = options.keys.iter().rev().find(|press| press.frame <= frame) {
+            key = press.key;
+        }
+        emulator.frame(key)?;
+    }
+
+    let lines = emulator.machine.state.video_buffer.iter()
+        .map(|line| (0 .. SCREEN_WIDTH).map(|x| if (line >> (SCREEN_WIDTH - 1 - x)) & 0x1 == 0x1 { '#' } else { '.' }).collect::<String>())
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n") + "\n")
+}
+
+/// The two screens side by side, with the rows that differ marked.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut out = format!("  {:<width$}   {}\n", "expected", "actual", width = SCREEN_WIDTH);
+    for row in 0 .. SCREEN_HEIGHT {
+        let expected = expected.lines().nth(row).unwrap_or_default();
+        let actual = actual.lines().nth(row).unwrap_or_default();
+        let marker = if expected == actual { ' ' } else { '>' };
+        out.push_str(&format!("{} {:<width$} | {}\n", marker, expected, actual, width = SCREEN_WIDTH));
+    }
+    out
+}
+
+#[test]
+pub fn roms_match_their_reference_screens() {
+    let golden = root().join("tests/golden");
+    let manifest: BTreeMap<String, RomOptions> = toml::from_str(&fs::read_to_string(golden.join("roms.toml")).unwrap())
+        .expect("tests/golden/roms.toml is valid");
+    let update = env::var_os("CHIP8_UPDATE_GOLDEN").is_some();
+
+    let mut failures = Vec::new();
+    for rom in roms() {
+        let name = rom.file_name().unwrap().to_string_lossy().to_string();
+        let default = RomOptions::default();
+        let options = manifest.get(&name).unwrap_or(&default);
+
+        let screens: Result<Vec<String>, String> = ENGINES.iter()
+            .map(|engine| {
+                panic::catch_unwind(|| screenshot(&rom, options, engine))
+                    .unwrap_or_else(|_| Err(format!("the emulator panicked under the {}", engine)))
+            })
+            .collect();
+        let screens = match screens {
+            Ok(screens) => screens,
+            Err(e) => {
+                failures.push(format!("{}: {}", name, e));
+                continue;
+            }
+        };
+        let actual = screens[0].clone();
+        if let Some((engine, screen)) = ENGINES.iter().zip(&screens).find(|(_, screen)| **screen != actual) {
+            failures.push(format!("{}: the {} changes the screen\n{}", name, engine, diff(&actual, screen)));
+            continue;
+        }
+
+        let reference = golden.join(format!("{}.txt", name));
+        if update {
+            fs::write(&reference, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&reference) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => failures.push(format!("{}: the screen differs\n{}", name, diff(&expected, &actual))),
+            Err(_) => failures.push(format!("{}: no reference screen, run with CHIP8_UPDATE_GOLDEN=1 to create one", name))
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,22 @@
+//! Builds the core without its default `std` feature, so nothing outside `core` and `alloc`
+//! creeps in unnoticed.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+pub fn core_builds_without_std() {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    // A target directory of its own, since the one running this test is locked
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no-std");
+
+    let output = Command::new(cargo)
+        .args(["check", "--lib", "--no-default-features"])
+        .arg("--manifest-path").arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .env("CARGO_TARGET_DIR", &target)
+        .output()
+        .expect("cargo runs");
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
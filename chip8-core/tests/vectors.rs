@@ -0,0 +1,69 @@
+//! Runs the single-instruction vectors in `tests/vectors/*.json` under each of their quirk
+//! profiles, through the interpreter and the recompiler. The bundled vectors come from
+//! `examples/generate_vectors.rs`; vector files from other emulators in the same format can be
+//! dropped into the directory.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use chip8_core::cpu::opcodes::{get_opcode, Opcode};
+use chip8_core::vectors::TestVector;
+
+fn load_vectors() -> Vec<(String, TestVector)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors");
+    let mut files: Vec<_> = fs::read_dir(&dir).expect("tests/vectors directory")
+        .map(|entry| entry.expect("tests/vectors entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    files.iter()
+        .flat_map(|path| {
+            let file = path.file_name().unwrap().to_string_lossy().to_string();
+            let vectors: Vec<TestVector> = serde_json::from_str(&fs::read_to_string(path).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", file, e));
+            vectors.into_iter().map(move |vector| (file.clone(), vector))
+        })
+        .collect()
+}
+
+/// The variant name of an opcode, e.g. `ADDVxVy`.
+fn variant(opcode: Opcode) -> String {
+    let debug = format!("{:?}", opcode);
+    debug.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
+}
+
+#[test]
+pub fn vectors_hold_under_their_profiles() {
+    let mut failures = Vec::new();
+    for (file, vector) in load_vectors() {
+        for profile in &vector.profiles {
+            if let Err(e) = vector.check(profile) {
+                failures.push(format!("{}: {} ({:04X}) under {}:\n{}", file, vector.name, vector.opcode, profile, e));
+            }
+            if let Err(e) = vector.check_recompiled(profile) {
+                failures.push(format!("{}: {} ({:04X}) recompiled under {}:\n{}", file, vector.name, vector.opcode, profile, e));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+pub fn vectors_cover_every_instruction() {
+    let covered: BTreeSet<String> = load_vectors().iter()
+        .map(|(_, vector)| variant(get_opcode(vector.opcode)))
+        .collect();
+
+    // Every known instruction decoded from some opcode, i.e. every variant but UNKNOWN
+    let all: BTreeSet<String> = (0 ..= 0xFFFF)
+        .map(get_opcode)
+        .filter(|opcode| !matches!(opcode, Opcode::UNKNOWN { .. }))
+        .map(variant)
+        .collect();
+
+    let missing: Vec<_> = all.difference(&covered).collect();
+    assert!(missing.is_empty(), "no vectors for {:?}", missing);
+}
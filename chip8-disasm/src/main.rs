@@ -1,32 +1,94 @@
 use std::env;
-use std::fs::File;
-use std::path::Path;
+use std::process;
 use std::vec::Vec;
 
-use chip8_core::cart::Cartridge;
-use chip8_core::cpu::{ProcState, MAX_MEMORY_SIZE};
-use std::rc::Rc;
-use std::cell::Cell;
+use chip8_core::cpu::opcodes::Opcode;
+use chip8_core::source::RomSource;
+use chip8_core::machine::Machine;
+use chip8_core::quirks::Platform;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
+use crate::xref::{format_xrefs, XrefTable};
+
+mod xref;
 
-    let mut f = File::open(&Path::new(filename)).expect(&format!("File not found: {}", filename));
+const USAGE: &str = "usage: chip8-disasm [--annotate] <rom>";
 
-    let cart = Cartridge::load(&mut f);
-    let mut mem = [0x0; MAX_MEMORY_SIZE];
-    for i in 0..cart.size {
-        mem[i + 0x200] = cart.buffer[i];
+/// What an instruction does and what it touches, for `--annotate`.
+fn annotate(opcode: &Opcode) -> String {
+    let mut facts = vec![opcode.description().to_string()];
+    if !opcode.reads().is_empty() {
+        facts.push(format!("reads {}", opcode.reads()));
+    }
+    if !opcode.writes().is_empty() {
+        facts.push(format!("writes {}", opcode.writes()));
+    }
+    if let Some(access) = opcode.memory() {
+        let verb = if access.write { "stores" } else { "loads" };
+        facts.push(format!("{} {} bytes at I", verb, access.len));
     }
-    let mut state = ProcState::new(mem, Rc::new(Cell::new(Option::None)));
+    if opcode.writes_i() {
+        facts.push("changes I".to_string());
+    }
+    if opcode.platforms().len() < Platform::ALL.len() {
+        let platforms: Vec<&str> = opcode.platforms().iter().map(|p| p.id()).collect();
+        facts.push(format!("platforms: {}", if platforms.is_empty() { "none".to_string() } else { platforms.join(", ") }));
+    }
+    facts.push(format!("{} VIP cycles", opcode.vip_cycles()));
+    facts.join("; ")
+}
 
-    println!("Cart Loaded. Size={} bytes", cart.size);
-    println!(" {:2} |  {:2}  | {}", "ADDR", "OP", "INSTRUCTION");
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let annotated = args.iter().any(|arg| arg == "--annotate" || arg == "-a");
+    let filename = match args.iter().find(|arg| !arg.starts_with('-')) {
+        Some(filename) => filename,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
 
+    let cart = match RomSource::parse(filename).load() {
+        Ok(cart) => cart,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            process::exit(1);
+        }
+    };
+    let mut state = Machine::builder(&cart).build().expect("a cartridge always fits at 0x200").state;
+
+    let mut listing = Vec::new();
     while (state.pc-0x200) < (cart.size as u16) {
         let current_addr = state.pc;
         let opcode = state.fetch_and_decode_opcode();
-        println!(" {:#04x} | {}", current_addr, opcode);
+        listing.push((current_addr, opcode));
+    }
+    let xrefs = XrefTable::build(&listing);
+
+    println!("Cart Loaded. Size={} bytes", cart.size);
+    println!(" ADDR |  OP  | INSTRUCTION");
+
+    for (current_addr, opcode) in &listing {
+        for addr in *current_addr ..= *current_addr + 1 {
+            let refs = xrefs.get(addr);
+            if !refs.is_empty() {
+                println!("        {}", format_xrefs(addr, refs));
+            }
+        }
+        if annotated {
+            println!(" {:#04x} | {:<22} ; {}", current_addr, opcode.to_string(), annotate(opcode));
+        } else {
+            println!(" {:#04x} | {}", current_addr, opcode);
+        }
+    }
+
+    let end = 0x200 + listing.len() as u16 * 2;
+    let outside: Vec<_> = xrefs.iter().filter(|(addr, _)| **addr < 0x200 || **addr >= end).collect();
+    if !outside.is_empty() {
+        println!();
+        println!("References outside the cartridge:");
+        for (addr, refs) in outside {
+            println!("        {}", format_xrefs(*addr, refs));
+        }
     }
-}
\ No newline at end of file
+}
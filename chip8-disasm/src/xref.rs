@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use chip8_core::cpu::metadata::Flow;
+use chip8_core::cpu::opcodes::Opcode;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum XrefKind {
+    Jump,
+    Call,
+    LoadI,
+    Read,
+    Write
+}
+
+impl Display for XrefKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            XrefKind::Jump => write!(f, "JP"),
+            XrefKind::Call => write!(f, "CALL"),
+            XrefKind::LoadI => write!(f, "LD I"),
+            XrefKind::Read => write!(f, "READ"),
+            XrefKind::Write => write!(f, "WRITE")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub struct Xref {
+    pub from: u16,
+    pub kind: XrefKind
+}
+
+impl Display for Xref {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} from {:#05x}", self.kind, self.from)
+    }
+}
+
+/// Maps every referenced address to the instructions that reference it.
+pub struct XrefTable {
+    refs: BTreeMap<u16, Vec<Xref>>
+}
+
+impl XrefTable {
+    /// Builds the table from a linear listing of `(address, opcode)` pairs.
+    ///
+    /// The value of I is tracked along the listing so that `LD [I], Vx`, `LD Vx, [I]` and
+    /// `LD B, Vx` can be attributed to an address. I is forgotten whenever it can no longer be
+    /// known statically: at jump and call targets (control may arrive from elsewhere), after
+    /// `ADD I, Vx` and `LD F, Vx`, after `LD [I], Vx` and `LD Vx, [I]` (which move I on some
+    /// platforms), after a `CALL` (the callee may change I), after `JP`, `JP V0` and `RET` (the
+    /// next instruction in the listing is not where control goes), and when the instruction
+    /// setting it may be skipped.
+    pub fn build(listing: &[(u16, Opcode)]) -> XrefTable {
+        let targets: BTreeSet<u16> = listing.iter()
+            .filter_map(|(_, op)| match op {
+                Opcode::JP{addr} | Opcode::CALL{addr} => Some(*addr),
+                _ => None
+            })
+            .collect();
+
+        let mut table = XrefTable { refs: BTreeMap::new() };
+        let mut ireg: Option<u16> = None;
+        let mut prev_is_skip = false;
+
+        for &(from, op) in listing {
+            if targets.contains(&from) {
+                ireg = None;
+            }
+
+            match op {
+                Opcode::JP{addr} => table.add(addr, from, XrefKind::Jump),
+                Opcode::CALL{addr} => table.add(addr, from, XrefKind::Call),
+                Opcode::LDIAddr{addr} => {
+                    table.add(addr, from, XrefKind::LoadI);
+                    ireg = if prev_is_skip { None } else { Some(addr) };
+                },
+                Opcode::ADDIVx{..} | Opcode::LDFVx{..} => ireg = None,
+                Opcode::LDIVx{x} => {
+                    table.add_range(ireg, x as u16 + 1, from, XrefKind::Write);
+                    ireg = None;
+                },
+                Opcode::LDVxI{x} => {
+                    table.add_range(ireg, x as u16 + 1, from, XrefKind::Read);
+                    ireg = None;
+                },
+                Opcode::LDBVx{..} => table.add_range(ireg, 3, from, XrefKind::Write),
+                _ => ()
+            }
+
+            if matches!(op.flow(), Flow::Jump(_) | Flow::IndirectJump(_) | Flow::Call(_) | Flow::Return) {
+                ireg = None;
+            }
+
+            prev_is_skip = op.flow() == Flow::Skip;
+        }
+
+        table
+    }
+
+    pub fn get(&self, addr: u16) -> &[Xref] {
+        self.refs.get(&addr).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u16, &Vec<Xref>)> {
+        self.refs.iter()
+    }
+
+    fn add(&mut self, addr: u16, from: u16, kind: XrefKind) {
+        let entry = self.refs.entry(addr).or_default();
+        let xref = Xref { from, kind };
+        if !entry.contains(&xref) {
+            entry.push(xref);
+        }
+    }
+
+    fn add_range(&mut self, start: Option<u16>, len: u16, from: u16, kind: XrefKind) {
+        if let Some(start) = start {
+            for addr in start .. start + len {
+                self.add(addr, from, kind);
+            }
+        }
+    }
+}
+
+pub fn format_xrefs(addr: u16, xrefs: &[Xref]) -> String {
+    let refs: Vec<String> = xrefs.iter().map(|x| x.to_string()).collect();
+    format!("; XREF {:#05x}: {}", addr, refs.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_core::cpu::opcodes::Opcode;
+
+    use super::*;
+
+    #[test]
+    pub fn jumps_and_calls_are_recorded_at_their_target() {
+        let listing = [
+            (0x200, Opcode::JP { addr: 0x206 }),
+            (0x202, Opcode::CALL { addr: 0x206 }),
+        ];
+
+        let table = XrefTable::build(&listing);
+
+        assert_eq!(table.get(0x206), &[
+            Xref { from: 0x200, kind: XrefKind::Jump },
+            Xref { from: 0x202, kind: XrefKind::Call },
+        ]);
+    }
+
+    #[test]
+    pub fn memory_access_through_known_i_is_recorded() {
+        let listing = [
+            (0x200, Opcode::LDIAddr { addr: 0x300 }),
+            (0x202, Opcode::LDBVx { x: 0 }),
+            (0x204, Opcode::LDIVx { x: 1 }),
+        ];
+
+        let table = XrefTable::build(&listing);
+
+        assert_eq!(table.get(0x300), &[
+            Xref { from: 0x200, kind: XrefKind::LoadI },
+            Xref { from: 0x202, kind: XrefKind::Write },
+            Xref { from: 0x204, kind: XrefKind::Write },
+        ]);
+        assert_eq!(table.get(0x302), &[Xref { from: 0x202, kind: XrefKind::Write }]);
+    }
+
+    #[test]
+    pub fn i_is_unknown_at_jump_targets() {
+        let listing = [
+            (0x200, Opcode::LDIAddr { addr: 0x300 }),
+            (0x202, Opcode::LDVxI { x: 0 }),
+            (0x204, Opcode::JP { addr: 0x202 }),
+        ];
+
+        let table = XrefTable::build(&listing);
+
+        assert_eq!(table.get(0x300), &[Xref { from: 0x200, kind: XrefKind::LoadI }]);
+    }
+
+    #[test]
+    pub fn i_is_unknown_when_load_may_be_skipped() {
+        let listing = [
+            (0x200, Opcode::SEVxByte { x: 0, byte: 1 }),
+            (0x202, Opcode::LDIAddr { addr: 0x300 }),
+            (0x204, Opcode::LDVxI { x: 0 }),
+        ];
+
+        let table = XrefTable::build(&listing);
+
+        assert_eq!(table.get(0x300), &[Xref { from: 0x202, kind: XrefKind::LoadI }]);
+    }
+
+    #[test]
+    pub fn i_is_unknown_after_a_call() {
+        let listing = [
+            (0x200, Opcode::LDIAddr { addr: 0x300 }),
+            (0x202, Opcode::CALL { addr: 0x400 }),
+            (0x204, Opcode::LDVxI { x: 0 }),
+        ];
+
+        let table = XrefTable::build(&listing);
+
+        assert_eq!(table.get(0x300), &[Xref { from: 0x200, kind: XrefKind::LoadI }]);
+    }
+
+    #[test]
+    pub fn i_does_not_carry_past_jumps_and_returns() {
+        for op in [Opcode::JP { addr: 0x400 }, Opcode::JPV0Addr { addr: 0x400 }, Opcode::RET] {
+            let listing = [
+                (0x200, Opcode::LDIAddr { addr: 0x300 }),
+                (0x202, op),
+                (0x204, Opcode::LDVxI { x: 0 }),
+            ];
+
+            let table = XrefTable::build(&listing);
+
+            assert_eq!(table.get(0x300), &[Xref { from: 0x200, kind: XrefKind::LoadI }], "{}", op);
+        }
+    }
+
+    #[test]
+    pub fn i_is_unknown_after_loads_and_stores_through_it() {
+        for op in [Opcode::LDIVx { x: 0 }, Opcode::LDVxI { x: 0 }] {
+            let listing = [
+                (0x200, Opcode::LDIAddr { addr: 0x300 }),
+                (0x202, op),
+                (0x204, Opcode::LDBVx { x: 0 }),
+            ];
+
+            let table = XrefTable::build(&listing);
+
+            assert_eq!(table.get(0x300).len(), 2, "{}", op);
+            assert!(table.get(0x300).iter().all(|xref| xref.from != 0x204), "{}", op);
+        }
+    }
+}
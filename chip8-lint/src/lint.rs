@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use chip8_core::cpu::opcodes::{get_opcode, op_nnn, Opcode};
+use chip8_core::cpu::{MAX_MEMORY_SIZE, MAX_STACK_SIZE, STARTING_PROGRAM_COUNTER};
+
+/// Calls that can be nested before `CALL` faults. The interpreter never uses the first stack
+/// slot, so this is one less than `MAX_STACK_SIZE`.
+pub const MAX_CALL_DEPTH: usize = MAX_STACK_SIZE - 1;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum LintKind {
+    UnknownOpcode { word: u16 },
+    SysCall { addr: u16 },
+    RecursiveCall { chain: Vec<u16> },
+    StackOverflow { depth: usize },
+    UnmatchedRet,
+    FontWrite { target: u16 },
+    OutOfBounds { target: u16, len: u16 },
+    SelfModifying { target: u16 }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Lint {
+    pub addr: u16,
+    pub kind: LintKind
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#05x}: ", self.addr)?;
+        match &self.kind {
+            LintKind::UnknownOpcode{word} => write!(f, "reachable {}", get_opcode(*word)),
+            LintKind::SysCall{addr} => write!(f, "SYS {:#05x} is ignored by modern interpreters", addr),
+            LintKind::RecursiveCall{chain} => {
+                let chain: Vec<String> = chain.iter().map(|a| format!("{:#05x}", a)).collect();
+                write!(f, "recursive CALL chain {} may overflow the stack", chain.join(" -> "))
+            },
+            LintKind::StackOverflow{depth} => {
+                write!(f, "CALL chain is {} deep, the stack only holds {}", depth, MAX_CALL_DEPTH)
+            },
+            LintKind::UnmatchedRet => write!(f, "RET is reachable without a matching CALL"),
+            LintKind::FontWrite{target} => write!(f, "writes into the font area at {:#05x}", target),
+            LintKind::OutOfBounds{target, len} => {
+                write!(f, "accesses {} bytes at {:#05x}, past the end of memory", len, target)
+            },
+            LintKind::SelfModifying{target} => write!(f, "modifies code at {:#05x}", target),
+        }
+    }
+}
+
+/// The statically known value of I, if there is one.
+type IState = Option<u16>;
+
+/// Everything learnt about a single routine: the program entry point or a `CALL` target.
+#[derive(Default)]
+struct Routine {
+    calls: BTreeMap<u16, u16>,
+    rets: BTreeSet<u16>
+}
+
+struct Linter<'a> {
+    mem: &'a [u8],
+    code: BTreeSet<u16>,
+    writes: BTreeMap<u16, (u16, u16)>,
+    routines: BTreeMap<u16, Routine>,
+    lints: BTreeSet<Lint>
+}
+
+/// Statically walks every path reachable from the program entry point and reports
+/// instructions that are likely to misbehave on real hardware or modern interpreters.
+pub fn lint(mem: &[u8; MAX_MEMORY_SIZE]) -> Vec<Lint> {
+    let mut linter = Linter {
+        mem,
+        code: BTreeSet::new(),
+        writes: BTreeMap::new(),
+        routines: BTreeMap::new(),
+        lints: BTreeSet::new()
+    };
+
+    let mut pending = vec![STARTING_PROGRAM_COUNTER];
+    while let Some(entry) = pending.pop() {
+        if linter.routines.contains_key(&entry) {
+            continue;
+        }
+        let routine = linter.walk(entry);
+        pending.extend(routine.calls.keys());
+        linter.routines.insert(entry, routine);
+    }
+
+    for ret in &linter.routines[&STARTING_PROGRAM_COUNTER].rets {
+        linter.lints.insert(Lint { addr: *ret, kind: LintKind::UnmatchedRet });
+    }
+
+    linter.check_call_depth();
+    linter.check_self_modification();
+
+    linter.lints.into_iter().collect()
+}
+
+impl<'a> Linter<'a> {
+    fn walk(&mut self, entry: u16) -> Routine {
+        let mut routine = Routine::default();
+        let mut visited: HashMap<u16, IState> = HashMap::new();
+        let mut worklist: Vec<(u16, IState)> = vec![(entry, None)];
+
+        while let Some((pc, ireg)) = worklist.pop() {
+            if pc as usize + 1 >= MAX_MEMORY_SIZE {
+                continue;
+            }
+
+            let ireg = match visited.get(&pc) {
+                Some(seen) if *seen == ireg || seen.is_none() => continue,
+                Some(_) => None,
+                None => ireg
+            };
+            visited.insert(pc, ireg);
+            self.code.insert(pc);
+
+            let word = (self.mem[pc as usize] as u16) << 8 | self.mem[pc as usize + 1] as u16;
+            let op = get_opcode(word);
+            let next = pc + 2;
+            match op {
+                Opcode::UNKNOWN{opcode: (0x0, _, _, _)} => {
+                    self.lint(pc, LintKind::SysCall { addr: op_nnn(word) });
+                    worklist.push((next, ireg));
+                },
+                Opcode::UNKNOWN{..} => self.lint(pc, LintKind::UnknownOpcode { word }),
+                Opcode::RET => { routine.rets.insert(pc); },
+                Opcode::JP{addr} => worklist.push((addr, ireg)),
+                Opcode::JPV0Addr{..} => (),
+                Opcode::CALL{addr} => {
+                    routine.calls.entry(addr).or_insert(pc);
+                    worklist.push((next, None));
+                },
+                Opcode::SEVxByte{..} | Opcode::SNEVxByte{..} | Opcode::SEVxVy{..} |
+                Opcode::SNEVxVy{..} | Opcode::SKPVx{..} | Opcode::SKNPVx{..} => {
+                    worklist.push((next, ireg));
+                    worklist.push((next + 2, ireg));
+                },
+                Opcode::LDIAddr{addr} => worklist.push((next, Some(addr))),
+                Opcode::ADDIVx{..} | Opcode::LDFVx{..} => worklist.push((next, None)),
+                // Both move I on some platforms, so it is not known afterwards
+                Opcode::LDIVx{x} => {
+                    self.access(pc, ireg, x as u16 + 1, true);
+                    worklist.push((next, None));
+                },
+                Opcode::LDBVx{..} => {
+                    self.access(pc, ireg, 3, true);
+                    worklist.push((next, ireg));
+                },
+                Opcode::LDVxI{x} => {
+                    self.access(pc, ireg, x as u16 + 1, false);
+                    worklist.push((next, None));
+                },
+                Opcode::DRW{nibble, ..} => {
+                    self.access(pc, ireg, nibble as u16, false);
+                    worklist.push((next, ireg));
+                },
+                _ => worklist.push((next, ireg))
+            }
+        }
+
+        routine
+    }
+
+    fn access(&mut self, pc: u16, ireg: IState, len: u16, write: bool) {
+        let target = match ireg {
+            Some(target) => target,
+            None => return
+        };
+
+        if target as usize + len as usize > MAX_MEMORY_SIZE {
+            self.lint(pc, LintKind::OutOfBounds { target, len });
+        }
+        if write {
+            if target < STARTING_PROGRAM_COUNTER {
+                self.lint(pc, LintKind::FontWrite { target });
+            }
+            self.writes.insert(pc, (target, len));
+        }
+    }
+
+    fn check_call_depth(&mut self) {
+        let mut chain = Vec::new();
+        let mut on_chain = HashSet::new();
+        let mut depths = HashMap::new();
+        let (depth, addr) = self.call_depth(STARTING_PROGRAM_COUNTER, &mut chain, &mut on_chain, &mut depths);
+
+        if depth > MAX_CALL_DEPTH {
+            self.lint(addr, LintKind::StackOverflow { depth });
+        }
+    }
+
+    /// The deepest chain of calls made from `routine`, and the `CALL` at the bottom of it. Each
+    /// routine is only worked out once, so layered call graphs do not blow up; calls back into
+    /// `chain` are reported as recursion instead of followed.
+    fn call_depth(&mut self, routine: u16, chain: &mut Vec<u16>, on_chain: &mut HashSet<u16>,
+                  depths: &mut HashMap<u16, (usize, u16)>) -> (usize, u16) {
+        if let Some(depth) = depths.get(&routine) {
+            return *depth;
+        }
+        chain.push(routine);
+        on_chain.insert(routine);
+
+        let calls: Vec<(u16, u16)> = self.routines[&routine].calls.iter().map(|(t, f)| (*t, *f)).collect();
+        let mut deepest = (0, routine);
+        for (target, from) in calls {
+            if on_chain.contains(&target) {
+                let start = chain.iter().position(|entry| *entry == target).unwrap();
+                let mut cycle = chain[start..].to_vec();
+                cycle.push(target);
+                self.lint(from, LintKind::RecursiveCall { chain: cycle });
+                continue;
+            }
+
+            let (depth, bottom) = self.call_depth(target, chain, on_chain, depths);
+            if depth + 1 > deepest.0 {
+                deepest = (depth + 1, if depth == 0 { from } else { bottom });
+            }
+        }
+
+        chain.pop();
+        on_chain.remove(&routine);
+        depths.insert(routine, deepest);
+        deepest
+    }
+
+    fn check_self_modification(&mut self) {
+        let writes: Vec<(u16, (u16, u16))> = self.writes.iter().map(|(pc, w)| (*pc, *w)).collect();
+        for (pc, (target, len)) in writes {
+            let start = target.saturating_sub(1);
+            let end = target.saturating_add(len);
+            if let Some(modified) = self.code.range(start .. end).next() {
+                let modified = (*modified).max(target);
+                self.lint(pc, LintKind::SelfModifying { target: modified });
+            }
+        }
+    }
+
+    fn lint(&mut self, addr: u16, kind: LintKind) {
+        self.lints.insert(Lint { addr, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_core::cpu::MAX_MEMORY_SIZE;
+
+    use super::*;
+
+    fn lint_program(program: &[u16]) -> Vec<LintKind> {
+        let mut mem = [0x0; MAX_MEMORY_SIZE];
+        for (i, op) in program.iter().enumerate() {
+            mem[0x200 + i * 2] = (op >> 8) as u8;
+            mem[0x200 + i * 2 + 1] = *op as u8;
+        }
+        lint(&mem).into_iter().map(|l| l.kind).collect()
+    }
+
+    #[test]
+    pub fn unknown_opcode_is_only_reported_when_reachable() {
+        assert_eq!(lint_program(&[0x1200, 0xFFFF]), vec![]);
+        assert_eq!(lint_program(&[0xFFFF]), vec![LintKind::UnknownOpcode { word: 0xFFFF }]);
+    }
+
+    #[test]
+    pub fn sys_call_is_reported() {
+        assert_eq!(lint_program(&[0x0123, 0x1202]), vec![LintKind::SysCall { addr: 0x123 }]);
+    }
+
+    #[test]
+    pub fn ret_outside_subroutine_is_reported() {
+        assert_eq!(lint_program(&[0x00EE]), vec![LintKind::UnmatchedRet]);
+        assert_eq!(lint_program(&[0x2204, 0x1202, 0x00EE]), vec![]);
+    }
+
+    #[test]
+    pub fn recursive_call_is_reported() {
+        assert_eq!(lint_program(&[0x2204, 0x1202, 0x2204, 0x00EE]),
+                   vec![LintKind::RecursiveCall { chain: vec![0x204, 0x204] }]);
+    }
+
+    #[test]
+    pub fn deep_call_chain_is_reported() {
+        let chain = |depth: u16| {
+            let mut program = Vec::new();
+            for i in 0 .. depth {
+                program.push(0x2000 | (0x200 + (i + 1) * 4));
+                program.push(0x00EE);
+            }
+            program.push(0x00EE);
+            lint_program(&program)
+        };
+
+        assert_eq!(chain(MAX_CALL_DEPTH as u16), vec![LintKind::UnmatchedRet]);
+        assert_eq!(chain(MAX_CALL_DEPTH as u16 + 1), vec![
+            LintKind::UnmatchedRet,
+            LintKind::StackOverflow { depth: MAX_CALL_DEPTH + 1 }
+        ]);
+    }
+
+    #[test]
+    pub fn layered_call_graphs_are_linted_quickly() {
+        // The entry point calls each of 10 routines, which each call all 10 of the next layer,
+        // 14 layers deep: far too many call chains to try one by one
+        const LAYERS: u16 = 14;
+        const WIDTH: u16 = 10;
+        let routine = |layer: u16, i: u16| 0x200 + 2 * (WIDTH + 1) + (layer * WIDTH + i) * 2 * (WIDTH + 1);
+        let mut program: Vec<u16> = (0 .. WIDTH).map(|i| 0x2000 | routine(0, i)).collect();
+        program.push(0x1000 | (0x200 + 2 * WIDTH));
+        for layer in 0 .. LAYERS {
+            for _ in 0 .. WIDTH {
+                if layer + 1 < LAYERS {
+                    program.extend((0 .. WIDTH).map(|i| 0x2000 | routine(layer + 1, i)));
+                } else {
+                    program.extend((0 .. WIDTH).map(|_| 0x6000));
+                }
+                program.push(0x00EE);
+            }
+        }
+
+        assert_eq!(lint_program(&program), vec![]);
+    }
+
+    #[test]
+    pub fn font_write_is_reported() {
+        assert_eq!(lint_program(&[0xA050, 0xF255, 0x1204]), vec![LintKind::FontWrite { target: 0x050 }]);
+    }
+
+    #[test]
+    pub fn i_is_unknown_after_loads_and_stores_through_it() {
+        // LD I, 0x04F; LD V0, [I] or LD [I], V0; LD [I], V0; JP 0x206
+        assert_eq!(lint_program(&[0xA04F, 0xF065, 0xF055, 0x1206]), vec![]);
+        assert_eq!(lint_program(&[0xA04F, 0xF355, 0xF055, 0x1206]),
+                   vec![LintKind::FontWrite { target: 0x04F }]);
+    }
+
+    #[test]
+    pub fn access_past_end_of_memory_is_reported() {
+        assert_eq!(lint_program(&[0xAFFE, 0xF265, 0x1204]),
+                   vec![LintKind::OutOfBounds { target: 0xFFE, len: 3 }]);
+    }
+
+    #[test]
+    pub fn self_modifying_code_is_reported() {
+        assert_eq!(lint_program(&[0xA206, 0xF033, 0x1206, 0x1206]),
+                   vec![LintKind::SelfModifying { target: 0x206 }]);
+    }
+}
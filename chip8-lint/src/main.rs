@@ -0,0 +1,34 @@
+use std::env;
+use std::process;
+use std::vec::Vec;
+
+use chip8_core::source::RomSource;
+use chip8_core::machine::Machine;
+
+use crate::lint::lint;
+
+mod lint;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let filename = &args[1];
+
+    let cart = match RomSource::parse(filename).load() {
+        Ok(cart) => cart,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            process::exit(2);
+        }
+    };
+    let machine = Machine::builder(&cart).build().expect("a cartridge always fits at 0x200");
+
+    let lints = lint(&machine.state.mem);
+    for l in &lints {
+        println!("warning: {}", l);
+    }
+    println!("{}: {} warning(s)", filename, lints.len());
+
+    if !lints.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+/// Terminals only report key presses, and repeats while a key is held, never releases. A key
+/// counts as held until `hold` passes without it being pressed again.
+pub struct HeldKey {
+    hold: Duration,
+    last: Option<(u8, Instant)>
+}
+
+impl HeldKey {
+    pub fn new(hold: Duration) -> HeldKey {
+        HeldKey { hold, last: None }
+    }
+
+    pub fn press(&mut self, key: u8, now: Instant) {
+        self.last = Some((key, now));
+    }
+
+    pub fn held(&self, now: Instant) -> Option<u8> {
+        match self.last {
+            Some((key, pressed)) if now.duration_since(pressed) < self.hold => Some(key),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn key_is_released_after_hold_timeout() {
+        let start = Instant::now();
+        let mut held = HeldKey::new(Duration::from_millis(100));
+
+        held.press(0x5, start);
+        assert_eq!(held.held(start + Duration::from_millis(50)), Some(0x5));
+
+        held.press(0x5, start + Duration::from_millis(80));
+        assert_eq!(held.held(start + Duration::from_millis(150)), Some(0x5));
+        assert_eq!(held.held(start + Duration::from_millis(180)), None);
+    }
+}
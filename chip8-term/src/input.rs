@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use chip8_core::frontend::Command;
+
+/// How long a lone Esc waits for the rest of an escape sequence before it counts as a key press.
+/// Terminals can split a sequence across reads, so it may arrive over several polls.
+pub const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Longest escape sequence kept while waiting for its final byte, anything longer is dropped.
+const MAX_SEQUENCE: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Key(u8),
+    Command(Command)
+}
+
+/// Turns the bytes read from the terminal into key presses and commands, holding on to escape
+/// sequences until they are complete.
+pub struct Decoder {
+    escape: Vec<u8>,
+    since: Instant
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder { escape: Vec::new(), since: Instant::now() }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8], now: Instant) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        if !self.escape.is_empty() && now.duration_since(self.since) >= ESCAPE_TIMEOUT {
+            self.finish_escape(&mut inputs);
+        }
+
+        for &byte in bytes {
+            // Ctrl-C, since raw mode turns off signals
+            if byte == 0x03 {
+                self.escape.clear();
+                inputs.push(Input::Command(Command::Quit));
+                continue;
+            }
+
+            if self.escape.is_empty() {
+                match byte {
+                    0x1B => {
+                        self.escape.push(byte);
+                        self.since = now;
+                    },
+                    _ => inputs.push(Input::Key(byte))
+                }
+                continue;
+            }
+
+            match (self.escape.get(1).copied(), byte) {
+                // Esc pressed twice, the first one on its own
+                (None, 0x1B) => {
+                    self.finish_escape(&mut inputs);
+                    self.escape.push(byte);
+                    self.since = now;
+                },
+                // Escape sequences sent by arrow and function keys
+                (None, b'[') | (None, b'O') => self.escape.push(byte),
+                // Alt held with another key, which is not a keypad press
+                (None, _) => self.escape.clear(),
+                (Some(_), _) => {
+                    self.escape.push(byte);
+                    if (0x40 ..= 0x7E).contains(&byte) {
+                        inputs.extend(hotkey(&self.escape[1..]).map(Input::Command));
+                        self.escape.clear();
+                    } else if self.escape.len() >= MAX_SEQUENCE {
+                        self.escape.clear();
+                    }
+                }
+            }
+        }
+
+        inputs
+    }
+
+    /// Gives up waiting on the escape sequence, an Esc on its own quits.
+    fn finish_escape(&mut self, inputs: &mut Vec<Input>) {
+        if self.escape.len() == 1 {
+            inputs.push(Input::Command(Command::Quit));
+        }
+        self.escape.clear();
+    }
+}
+
+/// Function keys that control the emulator, by the escape sequence after the initial Esc.
+fn hotkey(sequence: &[u8]) -> Option<Command> {
+    match sequence {
+        b"OQ" => Some(Command::Reset),
+        b"OR" => Some(Command::TogglePause),
+        b"OS" => Some(Command::FrameAdvance),
+        b"[15~" => Some(Command::SlowDown),
+        b"[17~" => Some(Command::SpeedUp),
+        b"[18~" => Some(Command::NormalSpeed),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn lone_escape_quits_only_after_timeout() {
+        let start = Instant::now();
+        let mut decoder = Decoder::new();
+
+        assert_eq!(decoder.decode(b"\x1b", start), vec![]);
+        assert_eq!(decoder.decode(b"", start + Duration::from_millis(10)), vec![]);
+        assert_eq!(decoder.decode(b"", start + ESCAPE_TIMEOUT), vec![Input::Command(Command::Quit)]);
+        assert_eq!(decoder.decode(b"", start + ESCAPE_TIMEOUT * 2), vec![]);
+    }
+
+    #[test]
+    pub fn escape_sequences_split_across_reads_are_joined() {
+        let start = Instant::now();
+        let mut decoder = Decoder::new();
+
+        assert_eq!(decoder.decode(b"1\x1b", start), vec![Input::Key(b'1')]);
+        assert_eq!(decoder.decode(b"[1", start + Duration::from_millis(5)), vec![]);
+        assert_eq!(decoder.decode(b"7~2", start + Duration::from_millis(10)),
+            vec![Input::Command(Command::SpeedUp), Input::Key(b'2')]);
+        assert_eq!(decoder.decode(b"\x1bOR", start), vec![Input::Command(Command::TogglePause)]);
+    }
+
+    #[test]
+    pub fn alt_chords_and_unknown_sequences_do_not_quit() {
+        let start = Instant::now();
+        let mut decoder = Decoder::new();
+
+        assert_eq!(decoder.decode(b"\x1bq", start), vec![]);
+        assert_eq!(decoder.decode(b"\x1b", start), vec![]);
+        assert_eq!(decoder.decode(b"x\x1b[A", start), vec![]);
+        assert_eq!(decoder.decode(b"", start + ESCAPE_TIMEOUT), vec![]);
+    }
+
+    #[test]
+    pub fn escape_pressed_twice_quits() {
+        let start = Instant::now();
+        let mut decoder = Decoder::new();
+
+        assert_eq!(decoder.decode(b"\x1b\x1b", start), vec![Input::Command(Command::Quit)]);
+        assert_eq!(decoder.decode(b"\x03", start), vec![Input::Command(Command::Quit)]);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::env;
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::{Duration, Instant};
+
+use chip8_core::color::Rgb;
+use chip8_core::db::RomDatabase;
+use chip8_core::display::{Filters, Frame};
+use chip8_core::frontend::{Audio, Command, Emulator, FrameTimer, Keypad, Video};
+use chip8_core::keymap::Keymap;
+use chip8_core::machine::Machine;
+use chip8_core::settings::Config;
+use chip8_core::source::RomSource;
+use chip8_core::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crate::hold::HeldKey;
+use crate::input::{Decoder, Input};
+use crate::render::{Glyphs, Renderer};
+use crate::tty::RawTerminal;
+
+mod hold;
+mod input;
+mod render;
+mod tty;
+
+const DEFAULT_TICKRATE: u32 = 10;
+const DEFAULT_HOLD_MS: u64 = 250;
+
+const USAGE: &str = "usage: chip8-term <rom> [--braille] [--keys <layout>] [--hold <ms>] [--fg <colour>] [--bg <colour>] [--filter <filters>] [--vip-timing]";
+
+struct Options {
+    rom: String,
+    glyphs: Glyphs,
+    keymap: Option<Keymap>,
+    hold: Duration,
+    foreground: Option<Rgb>,
+    background: Option<Rgb>,
+    filters: Filters,
+    vip_timing: bool
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        glyphs: Glyphs::HalfBlock,
+        keymap: None,
+        hold: Duration::from_millis(DEFAULT_HOLD_MS),
+        foreground: None,
+        background: None,
+        filters: Filters::default(),
+        vip_timing: false
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--braille" => options.glyphs = Glyphs::Braille,
+            "--vip-timing" => options.vip_timing = true,
+            "--keys" => options.keymap = Some(value()?.parse()?),
+            "--hold" => {
+                let ms = value()?;
+                options.hold = Duration::from_millis(ms.parse().map_err(|_| format!("invalid hold time: {}", ms))?);
+            },
+            "--fg" => options.foreground = Some(value()?.parse()?),
+            "--bg" => options.background = Some(value()?.parse()?),
+            "--filter" => options.filters = value()?.parse()?,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg))
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| USAGE.to_string())?;
+    Ok(options)
+}
+
+struct TermVideo {
+    renderer: Renderer,
+    out: io::Stdout
+}
+
+impl Video for TermVideo {
+    fn present(&mut self, frame: &Frame) -> Result<(), String> {
+        let mut escapes = String::new();
+        self.renderer.render(|x, y| frame.is_lit(x, y), &mut escapes);
+
+        if escapes.is_empty() {
+            return Ok(());
+        }
+        self.out.write_all(escapes.as_bytes())
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    fn set_status(&mut self, status: &str) -> Result<(), String> {
+        // On the line below the drawing, cleared to the end
+        let (_, rows) = self.renderer.size();
+        write!(self.out, "\x1b[{};1H\x1b[0m{}\x1b[K", rows + 2, status)
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+struct TermKeypad {
+    tty: RawTerminal,
+    keymap: Keymap,
+    held: HeldKey,
+    decoder: Decoder,
+    pending: VecDeque<Command>
+}
+
+impl Keypad for TermKeypad {
+    fn poll(&mut self) -> Option<Command> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+
+        let input = self.tty.read_pending();
+        let now = Instant::now();
+        for input in self.decoder.decode(&input, now) {
+            match input {
+                Input::Command(command) => self.pending.push_back(command),
+                Input::Key(byte) => if let Some(key) = self.keymap.get(&(byte as char).to_string()) {
+                    self.held.press(key, now);
+                }
+            }
+        }
+
+        self.pending.pop_front()
+    }
+
+    fn held(&self) -> Option<u8> {
+        self.held.held(Instant::now())
+    }
+}
+
+/// Rings the terminal bell whenever the buzzer starts.
+struct Bell {
+    on: bool
+}
+
+impl Audio for Bell {
+    fn set_tone(&mut self, on: bool) {
+        if on && !self.on {
+            let mut out = io::stdout();
+            let _ = out.write_all(b"\x07").and_then(|_| out.flush());
+        }
+        self.on = on;
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let source = RomSource::parse(&options.rom);
+    let cart = source.load().map_err(|e| format!("{}: {}", source, e))?;
+
+    let db = RomDatabase::bundled();
+    let info = db.lookup(&cart);
+    let platform = info.and_then(|i| i.platform).or_else(|| source.platform());
+
+    let mut builder = Machine::builder(&cart).platform(platform).vip_timing(options.vip_timing);
+    if let Some(info) = info {
+        builder = builder.quirks(info.quirks);
+    }
+    let machine = builder.build().map_err(|e| format!("{}: {}", source, e))?;
+
+    // The same settings file as the chip8 binary, with the ROM's own settings beating the database
+    let config = match Config::default_path() {
+        Some(path) => Config::load(&path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Config::default()
+    };
+    let sha1 = cart.sha1();
+    let rom = config.rom_settings(&sha1, source.file_name());
+
+    let tickrate = rom.ipf
+        .or_else(|| info.and_then(|i| i.tickrate))
+        .or(config.global.ipf)
+        .or_else(|| platform.map(|p| p.tickrate()))
+        .unwrap_or(DEFAULT_TICKRATE);
+    let colors = match (rom.palette, info.map(|i| i.colors.as_slice()).unwrap_or_default()) {
+        (Some((background, foreground)), _) => vec![background, foreground],
+        (None, []) => config.global.palette.map(|(background, foreground)| vec![background, foreground]).unwrap_or_default(),
+        (None, colors) => colors.to_vec()
+    };
+    let background = options.background.or_else(|| colors.first().copied()).unwrap_or(Rgb::BLACK);
+    let foreground = options.foreground.or_else(|| colors.get(1).copied()).unwrap_or(Rgb::WHITE);
+
+    // A layout given on the command line wins over the config file
+    let keymap = options.keymap
+        .unwrap_or_else(|| config.keymap_for(&Keymap::qwerty(), &Keymap::default(), &sha1, source.file_name()));
+
+    let tty = RawTerminal::enable().map_err(|e| format!("cannot use the terminal: {}", e))?;
+    let mut video = TermVideo {
+        renderer: Renderer::new(options.glyphs, SCREEN_WIDTH, SCREEN_HEIGHT, foreground, background),
+        out: io::stdout()
+    };
+    let mut keypad = TermKeypad { tty, keymap, held: HeldKey::new(options.hold), decoder: Decoder::new(), pending: VecDeque::new() };
+
+    let mut emulator = Emulator::new(machine, tickrate).filters(options.filters);
+    // There is no way to pick another ROM from the terminal, so any exit is final
+    emulator.run(&mut video, &mut keypad, &mut Bell { on: false }, &mut FrameTimer::new()).map(|_| ())
+}
@@ -0,0 +1,158 @@
+use std::fmt::Write;
+
+use chip8_core::color::Rgb;
+
+/// How screen pixels are packed into terminal character cells.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Glyphs {
+    /// One column by two rows per cell, using the upper and lower half block characters.
+    HalfBlock,
+    /// Two columns by four rows per cell, using braille patterns.
+    Braille
+}
+
+impl Glyphs {
+    /// The width and height in pixels of one character cell.
+    fn cell_size(&self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4)
+        }
+    }
+
+    /// The character for a cell, given its pixels relative to the top left corner.
+    fn glyph(&self, pixel: impl Fn(usize, usize) -> bool) -> char {
+        match self {
+            Glyphs::HalfBlock => match (pixel(0, 0), pixel(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '\u{2580}',
+                (false, true) => '\u{2584}',
+                (true, true) => '\u{2588}'
+            },
+            Glyphs::Braille => {
+                // Dot numbering of the Unicode braille block, column by column
+                const DOTS: [(usize, usize, u32); 8] = [
+                    (0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08),
+                    (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80)
+                ];
+                let bits = DOTS.iter()
+                    .filter(|(x, y, _)| pixel(*x, *y))
+                    .fold(0, |bits, (_, _, bit)| bits | bit);
+                std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+            }
+        }
+    }
+}
+
+/// Turns frames into ANSI escape sequences, redrawing only the cells that changed since the
+/// previous frame.
+pub struct Renderer {
+    glyphs: Glyphs,
+    width: usize,
+    height: usize,
+    foreground: Rgb,
+    background: Rgb,
+    /// What is currently on the terminal, `None` for cells that have never been drawn.
+    cells: Vec<Option<char>>
+}
+
+impl Renderer {
+    /// A renderer for a `width` by `height` pixel screen, such as 64x32 or 128x64.
+    pub fn new(glyphs: Glyphs, width: usize, height: usize, foreground: Rgb, background: Rgb) -> Renderer {
+        let mut renderer = Renderer { glyphs, width, height, foreground, background, cells: Vec::new() };
+        let (columns, rows) = renderer.size();
+        renderer.cells = vec![None; columns * rows];
+        renderer
+    }
+
+    /// The size of the drawing in character cells.
+    pub fn size(&self) -> (usize, usize) {
+        let (cell_width, cell_height) = self.glyphs.cell_size();
+        (self.width.div_ceil(cell_width), self.height.div_ceil(cell_height))
+    }
+
+    /// Appends the escape sequences that bring the terminal up to date with the frame described
+    /// by `pixel(x, y)` to `out`.
+    pub fn render(&mut self, pixel: impl Fn(usize, usize) -> bool, out: &mut String) {
+        let (cell_width, cell_height) = self.glyphs.cell_size();
+        let (columns, rows) = self.size();
+        let (width, height) = (self.width, self.height);
+        let mut cursor = None;
+
+        for row in 0 .. rows {
+            for column in 0 .. columns {
+                let left = column * cell_width;
+                let top = row * cell_height;
+                let glyph = self.glyphs.glyph(|x, y| {
+                    left + x < width && top + y < height && pixel(left + x, top + y)
+                });
+
+                let cell = &mut self.cells[row * columns + column];
+                if *cell == Some(glyph) {
+                    continue;
+                }
+                *cell = Some(glyph);
+
+                if cursor.is_none() {
+                    let (fg, bg) = (self.foreground, self.background);
+                    let _ = write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fg.r, fg.g, fg.b, bg.r, bg.g, bg.b);
+                }
+                if cursor != Some((row, column)) {
+                    let _ = write!(out, "\x1b[{};{}H", row + 1, column + 1);
+                }
+                out.push(glyph);
+                cursor = Some((row, column + 1));
+            }
+        }
+
+        if cursor.is_some() {
+            out.push_str("\x1b[0m");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(glyphs: Glyphs, width: usize, height: usize) -> Renderer {
+        Renderer::new(glyphs, width, height, Rgb::WHITE, Rgb::BLACK)
+    }
+
+    #[test]
+    pub fn half_blocks_pack_two_rows_per_cell() {
+        let mut out = String::new();
+        let mut renderer = renderer(Glyphs::HalfBlock, 2, 2);
+
+        renderer.render(|x, y| (x, y) == (0, 0) || x == 1, &mut out);
+
+        assert_eq!(out, "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m\x1b[1;1H\u{2580}\u{2588}\x1b[0m");
+    }
+
+    #[test]
+    pub fn braille_packs_eight_pixels_per_cell() {
+        let mut out = String::new();
+        let mut renderer = renderer(Glyphs::Braille, 2, 4);
+
+        renderer.render(|x, y| x == 0 && y != 3, &mut out);
+
+        assert!(out.contains('\u{2807}'));
+    }
+
+    #[test]
+    pub fn only_changed_cells_are_redrawn() {
+        let mut out = String::new();
+        let mut renderer = renderer(Glyphs::HalfBlock, 128, 64);
+        assert_eq!(renderer.size(), (128, 32));
+
+        renderer.render(|_, _| false, &mut out);
+        out.clear();
+        renderer.render(|x, y| (x, y) == (100, 41), &mut out);
+
+        assert_eq!(out, "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m\x1b[21;101H\u{2584}\x1b[0m");
+
+        out.clear();
+        renderer.render(|x, y| (x, y) == (100, 41), &mut out);
+        assert_eq!(out, "");
+    }
+}
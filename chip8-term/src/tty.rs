@@ -0,0 +1,59 @@
+use std::io;
+use std::io::Write;
+use std::mem;
+
+/// Puts the terminal in raw, non-blocking mode on the alternate screen for as long as it lives.
+pub struct RawTerminal {
+    original: libc::termios
+}
+
+impl RawTerminal {
+    pub fn enable() -> io::Result<RawTerminal> {
+        let original = unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios
+        };
+
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // Reads return immediately with whatever is available
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Alternate screen, hidden cursor, cleared
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+
+        Ok(RawTerminal { original })
+    }
+
+    /// Reads whatever input is pending without blocking.
+    pub fn read_pending(&self) -> Vec<u8> {
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 64];
+
+        loop {
+            let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if read <= 0 {
+                return pending;
+            }
+            pending.extend_from_slice(&buf[.. read as usize]);
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
@@ -0,0 +1,37 @@
+//! Loads arbitrary bytes as a binary ROM, a hex dump and an Intel HEX file, then boots whatever
+//! loads and runs it for a few frames. Malformed ROMs must be rejected with an error.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8_core::cart::Cartridge;
+use chip8_core::machine::Machine;
+
+const STEPS: usize = 1000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut carts = vec![Cartridge::load(&mut &data[..]), Cartridge::from_bytes(data)];
+    if let Ok(text) = std::str::from_utf8(data) {
+        carts.push(Cartridge::from_hex_text(text));
+        carts.push(Cartridge::from_intel_hex(text));
+    }
+
+    for cart in carts.into_iter().flatten() {
+        assert!(cart.size > 0 && cart.size <= cart.buffer.len());
+        cart.sha1();
+
+        let mut machine = match Machine::builder(&cart).seed(Some(0)).build() {
+            Ok(machine) => machine,
+            Err(_) => continue
+        };
+        for step in 0 .. STEPS {
+            if machine.try_step().is_err() {
+                break;
+            }
+            if step % 10 == 0 {
+                machine.state.clock_tick(1);
+            }
+        }
+    }
+});
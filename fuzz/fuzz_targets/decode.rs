@@ -0,0 +1,22 @@
+//! Decodes arbitrary instruction words. Every word decodes to something, the same thing every
+//! time, and encodes and prints to something that reads back the same.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8_core::cpu::opcodes::{get_opcode, split_opcode, Opcode};
+
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(2).map(|pair| (pair[0] as u16) << 8 | pair[1] as u16) {
+        let opcode = get_opcode(word);
+        assert_eq!(get_opcode(word), opcode);
+
+        if let Opcode::UNKNOWN { opcode: nibbles } = opcode {
+            assert_eq!(nibbles, split_opcode(word));
+        }
+
+        assert_eq!(opcode.encode(), word);
+        assert_eq!(opcode.to_string().parse::<Opcode>(), Ok(opcode));
+    }
+});
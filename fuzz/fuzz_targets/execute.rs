@@ -0,0 +1,80 @@
+//! Runs arbitrary instruction streams from arbitrary machine states under arbitrary quirks.
+//! Faults such as an unknown opcode or a stack overflow stop the run; anything else, including
+//! registers and addresses that run off the end of memory, must not panic.
+
+#![no_main]
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+use chip8_core::cpu::{ProcState, MAX_MEMORY_SIZE, MAX_STACK_SIZE};
+use chip8_core::quirks::Quirks;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    vreg: [u8; 16],
+    ireg: u16,
+    pc: u16,
+    sp: u8,
+    stack: [u16; MAX_STACK_SIZE],
+    delay_t: u8,
+    sound_t: u8,
+    key: Option<u8>,
+    vblank: bool,
+    quirks: [bool; 7],
+    font_address: u16,
+    seed: Option<u64>,
+    /// Instructions to run, and 60Hz ticks between them.
+    steps: Vec<Step>,
+    /// The start of memory, the rest is zero.
+    memory: Vec<u8>
+}
+
+#[derive(Debug, Arbitrary)]
+enum Step {
+    Execute,
+    Tick,
+    Key(Option<u8>)
+}
+
+fuzz_target!(|input: Input| {
+    let mut mem = [0x0; MAX_MEMORY_SIZE];
+    let len = input.memory.len().min(MAX_MEMORY_SIZE);
+    mem[.. len].copy_from_slice(&input.memory[.. len]);
+
+    let keypad = Rc::new(Cell::new(input.key));
+    let mut state = ProcState::new(mem, keypad.clone());
+    state.vreg = input.vreg;
+    state.ireg = input.ireg;
+    state.pc = input.pc;
+    state.sp = input.sp as usize % MAX_STACK_SIZE;
+    state.stack = input.stack;
+    state.delay_t = input.delay_t;
+    state.sound_t = input.sound_t;
+    state.vblank = input.vblank;
+    state.font_address = input.font_address;
+    state.seed(input.seed.unwrap_or_default());
+
+    let mut quirks = Quirks::default();
+    for (name, on) in Quirks::NAMES.iter().zip(input.quirks.iter()) {
+        quirks.set(name, *on).unwrap();
+    }
+    state.quirks = quirks;
+
+    for step in input.steps {
+        match step {
+            Step::Execute => {
+                let opcode = state.fetch_and_decode_opcode();
+                if state.try_execute_opcode(opcode).is_err() {
+                    return;
+                }
+            },
+            Step::Tick => state.clock_tick(1),
+            Step::Key(key) => keypad.set(key)
+        }
+        assert!(state.sp < MAX_STACK_SIZE);
+    }
+});
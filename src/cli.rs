@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chip8_core::color::Rgb;
+use chip8_core::display::Filters;
+use chip8_core::frontend::{Scaling, FRAME_DURATION};
+use chip8_core::quirks::{Platform, Quirks};
+use chip8_core::settings::Settings;
+
+pub const USAGE: &str = "usage: chip8 [options] <rom>";
+
+pub const HELP: &str = "\
+Runs a CHIP-8 ROM from a file, a zip archive (games.zip or games.zip:pong.ch8) or - for stdin.
+
+options:
+  --ips <n>                  instructions per second, a multiple of 60
+  --ipf <n>                  instructions per 60Hz frame
+  --vip-timing               gives each instruction its COSMAC VIP time instead of a flat
+                             number per frame
+  --platform <id>            originalChip8, hybridVIP, modernChip8, chip48, superchip1,
+                             superchip or xochip, instead of the one the database suggests
+  --quirk <name>[=on|off]    turns a quirk on or off: shift, memoryIncrementByX,
+                             memoryLeaveIUnchanged, wrap, jump, vblank or logic
+  --scale <n>                initial window size as a multiple of 64x32
+  --scaling <mode>           integer, aspect or stretch: how the screen fills the window
+  --no-vsync                 shows frames without waiting for the display's refresh
+  --filter <list>            flicker filters, e.g. blend=3,phosphor=0.7,vblank: blends the
+                             last frames, fades pixels out, or shows only finished frames
+  --palette <bg>,<fg>        colours, e.g. #000000,#33ff66
+  --font <id|file>           vip, dream6800, eti660, schip or fish, or a raw font file
+  --font-address <addr>      where the font is loaded, 0 unless changed, e.g. 0x50
+  --seed <n>                 makes the random numbers the same on every run
+  --trace <file>             writes every executed instruction to the file, - for stdout
+  --fullscreen               starts in fullscreen
+  --windowed                 starts in a window, even if the config says fullscreen
+  --mute                     turns the buzzer off
+  --no-mute                  turns the buzzer on, even if the config says mute
+  --config <file>            settings file instead of ~/.config/chip8/config.toml
+  --save                     saves the settings given here as the defaults for every ROM
+  --save-rom                 saves the settings given here for this ROM only
+  --help                     shows this message
+
+Settings in the config file are overridden by those for the ROM in its [roms.\"<sha1>\"] or
+[roms.\"<file name>\"] table, then by the environment, and all of them by the command line. Keys
+remapped with F1 are saved as keys in the ROM's table.
+
+environment:
+  CHIP8_DATABASE             extra ROM database to read on top of the bundled one
+  CHIP8_PALETTE              like --palette
+  CHIP8_SCALING              like --scaling
+  CHIP8_FILTERS              like --filter
+  CHIP8_FONT                 like --font
+
+keys:
+  Esc quit, F1 remap keys, F2 reset, F3 pause, F4 frame advance,
+  F5 slower, F6 faster, F7 normal speed, F11 fullscreen.
+  Drop a ROM on the window to load it.";
+
+/// Settings given on the command line. Anything left unset falls back to the environment, the
+/// ROM database or the defaults.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub rom: Option<String>,
+    /// Instructions per frame.
+    pub tickrate: Option<u32>,
+    pub vip_timing: bool,
+    pub platform: Option<Platform>,
+    /// Quirks to change, by database name, after the platform's have been chosen.
+    pub quirks: Vec<(String, bool)>,
+    pub scale: Option<u32>,
+    pub scaling: Option<Scaling>,
+    /// `Some(false)` to turn vsync off.
+    pub vsync: Option<bool>,
+    pub filters: Option<Filters>,
+    pub palette: Option<(Rgb, Rgb)>,
+    /// The id of a bundled font or the path of a raw font file.
+    pub font: Option<String>,
+    pub font_address: Option<u16>,
+    pub seed: Option<u64>,
+    pub trace: Option<PathBuf>,
+    pub fullscreen: Option<bool>,
+    pub mute: Option<bool>,
+    pub config: Option<PathBuf>,
+    pub save: bool,
+    pub save_rom: bool,
+    pub help: bool
+}
+
+impl Options {
+    /// The options that can also be given in the config file.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            ipf: self.tickrate,
+            palette: self.palette,
+            scale: self.scale,
+            scaling: self.scaling,
+            vsync: self.vsync,
+            filters: self.filters,
+            fullscreen: self.fullscreen,
+            mute: self.mute,
+            font: self.font.clone(),
+            font_address: self.font_address,
+            ..Settings::default()
+        }
+    }
+
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        for (name, value) in &self.quirks {
+            // Names were checked while parsing
+            let _ = quirks.set(name, *value);
+        }
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--ips" => {
+                // Instructions run a whole number at a time every frame
+                let ips = positive(arg, value()?)?;
+                let fps = (1.0 / FRAME_DURATION.as_secs_f64()).round() as u32;
+                if ips % fps != 0 {
+                    return Err(format!("{} must be a multiple of {}, the frame rate", arg, fps));
+                }
+                options.tickrate = Some(ips / fps);
+            },
+            "--ipf" => options.tickrate = Some(positive(arg, value()?)?),
+            "--vip-timing" => options.vip_timing = true,
+            "--platform" => options.platform = Some(value()?.parse()?),
+            "--quirk" => options.quirks.push(parse_quirk(value()?)?),
+            "--scale" => options.scale = Some(positive(arg, value()?)?),
+            "--scaling" => options.scaling = Some(value()?.parse()?),
+            "--no-vsync" => options.vsync = Some(false),
+            "--filter" => options.filters = Some(value()?.parse()?),
+            "--palette" => options.palette = Some(parse_palette(value()?)?),
+            "--font" => options.font = Some(value()?.clone()),
+            "--font-address" => options.font_address = Some(address(arg, value()?)?),
+            "--seed" => options.seed = Some(number(arg, value()?)?),
+            "--trace" => options.trace = Some(PathBuf::from(value()?)),
+            "--fullscreen" => options.fullscreen = Some(true),
+            "--windowed" => options.fullscreen = Some(false),
+            "--mute" => options.mute = Some(true),
+            "--no-mute" => options.mute = Some(false),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--save" => options.save = true,
+            "--save-rom" => options.save_rom = true,
+            "--help" | "-h" => options.help = true,
+            _ if options.rom.is_none() && !arg.starts_with("--") => options.rom = Some(arg.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => return Err(format!("unexpected argument: {}", arg))
+        }
+    }
+
+    if options.rom.is_none() && !options.help {
+        return Err("no ROM given".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value for {}: {}", option, value))
+}
+
+/// A decimal address, or hexadecimal with a `0x` prefix.
+fn address(option: &str, value: &str) -> Result<u16, String> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).map_err(|_| format!("invalid value for {}: {}", option, value)),
+        None => number(option, value)
+    }
+}
+
+fn positive(option: &str, value: &str) -> Result<u32, String> {
+    match number(option, value)? {
+        0 => Err(format!("{} must be at least 1", option)),
+        n => Ok(n)
+    }
+}
+
+fn parse_quirk(quirk: &str) -> Result<(String, bool), String> {
+    let (name, value) = match quirk.find('=') {
+        Some(idx) => (&quirk[.. idx], &quirk[idx + 1 ..]),
+        None => (quirk, "on")
+    };
+    let value = match value {
+        "on" | "true" | "1" => true,
+        "off" | "false" | "0" => false,
+        _ => return Err(format!("invalid quirk value: {}", quirk))
+    };
+
+    Quirks::default().set(name, value)?;
+    Ok((name.to_string(), value))
+}
+
+/// "background,foreground", e.g. "#000000,#33ff66"
+pub fn parse_palette(palette: &str) -> Result<(Rgb, Rgb), String> {
+    match palette.split(',').collect::<Vec<_>>().as_slice() {
+        [background, foreground] => Ok((background.trim().parse()?, foreground.trim().parse()?)),
+        _ => Err(format!("invalid palette: {}", palette))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    pub fn options_are_parsed_around_the_rom() {
+        let options = parse_args(&args("--ips 600 pong.ch8 --platform chip48 --quirk wrap --quirk shift=off --seed 7 --mute --vip-timing --font eti660 --font-address 0x50 --scaling aspect --no-vsync --filter blend=2")).unwrap();
+
+        assert_eq!(options.rom.as_deref(), Some("pong.ch8"));
+        assert_eq!(options.tickrate, Some(10));
+        assert_eq!(options.platform, Some(Platform::Chip48));
+        assert_eq!(options.quirks, vec![("wrap".to_string(), true), ("shift".to_string(), false)]);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!((options.mute, options.fullscreen), (Some(true), None));
+        assert!(options.vip_timing);
+        assert_eq!((options.font.as_deref(), options.font_address), (Some("eti660"), Some(0x50)));
+        assert_eq!((options.scaling, options.vsync), (Some(Scaling::Aspect), Some(false)));
+        assert_eq!(options.filters.map(|f| f.blend_frames), Some(2));
+
+        let settings = options.settings();
+        assert_eq!((settings.ipf, settings.mute, settings.fullscreen), (Some(10), Some(true), None));
+    }
+
+    #[test]
+    pub fn config_switches_can_be_turned_off() {
+        let settings = parse_args(&args("pong.ch8 --fullscreen --mute --windowed --no-mute")).unwrap().settings();
+
+        assert_eq!((settings.fullscreen, settings.mute), (Some(false), Some(false)));
+    }
+
+    #[test]
+    pub fn bad_arguments_are_reported() {
+        assert_eq!(parse_args(&args("")), Err("no ROM given".to_string()));
+        assert_eq!(parse_args(&args("pong.ch8 --ipf")), Err("--ipf needs a value".to_string()));
+        assert_eq!(parse_args(&args("pong.ch8 --scale 0")), Err("--scale must be at least 1".to_string()));
+        assert_eq!(parse_args(&args("pong.ch8 --ips 100")), Err("--ips must be a multiple of 60, the frame rate".to_string()));
+        assert_eq!(parse_args(&args("pong.ch8 --turbo")), Err("unknown option: --turbo".to_string()));
+        assert!(parse_args(&args("pong.ch8 --quirk fastDraw")).is_err());
+        assert_eq!(parse_args(&args("pong.ch8 --font-address 0xG0")), Err("invalid value for --font-address: 0xG0".to_string()));
+        assert!(parse_args(&args("--help")).unwrap().help);
+    }
+}
@@ -1,138 +1,249 @@
-use std::cell::Cell;
 use std::env;
 use std::fs::File;
-use std::path::Path;
-use std::rc::Rc;
-
-use sdl2::event::Event;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-
-use chip8_core::cart::Cartridge;
-use chip8_core::cpu::{MAX_MEMORY_SIZE, ProcState};
-use chip8_core::font::FONT_SPRITES;
-use std::time::Duration;
-use sdl2::video::Window;
-use sdl2::rect::Rect;
-use sdl2::pixels::Color;
-use chip8_core::{SCREEN_WIDTH, SCREEN_HEIGHT};
-
-const SCALING_FACTOR: u32 = 12;
-
-pub fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    let delay = args[2].parse::<u64>().unwrap();
-    let io = Rc::new(Cell::new(Option::None));
-    let mut state = start_emu(&filename, io.clone());
-
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-
-    let title = Path::new(&filename).file_name().and_then(|f| f.to_str()).unwrap_or("Unknown");
-    let width = (SCREEN_WIDTH as u32) * SCALING_FACTOR;
-    let height = (SCREEN_HEIGHT as u32) * SCALING_FACTOR;
-    let mut window = video_subsystem.window(title, width, height)
-        .position_centered()
-        .build()
-        .map_err(|e| e.to_string())?;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip8_core::db::{RomDatabase, RomInfo};
+use chip8_core::font::{Font, SmallFont};
+use chip8_core::frontend::{Emulator, Exit, FrameTimer};
+use chip8_core::keymap::Keymap;
+use chip8_core::machine::Machine;
+use chip8_core::settings::{Config, Settings};
+use chip8_core::sha1::sha1_hex;
+use chip8_core::source::RomSource;
+
+use crate::cli::{parse_args, parse_palette, Options, HELP, USAGE};
+use crate::sdl::{action_keymap, pad_keymap, OnRemap, SdlAudio, SdlKeypad, SdlVideo, VideoOptions};
+
+mod cli;
+mod sdl;
+
+const DEFAULT_TICKRATE: u32 = 10;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("chip8: {}\n{}\ntry 'chip8 --help' for more", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}\n\n{}", USAGE, HELP);
+        return;
+    }
 
-    let mut events = sdl_context.event_pump()?;
+    if let Err(e) = run(options) {
+        eprintln!("chip8: {}", e);
+        process::exit(1);
+    }
+}
 
-    'main: loop {
-        poll_keys(&mut events, io.clone());
+fn run(options: Options) -> Result<(), String> {
+    let mut source = RomSource::parse(options.rom.as_deref().unwrap_or("-"));
 
-        // Audio
+    let mut db = RomDatabase::bundled();
+    if let Ok(path) = env::var("CHIP8_DATABASE") {
+        db.extend(RomDatabase::load(Path::new(&path)).map_err(|e| format!("{}: {}", path, e))?);
+    }
 
-        let opcode = state.fetch_and_decode_opcode();
-        state.execute_opcode(opcode);
+    let config_path = options.config.clone().or_else(Config::default_path);
+    let mut config = match &config_path {
+        Some(path) => Config::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Config::default()
+    };
+    // The environment and then the command line override the config file
+    let mut overrides = Settings::default();
+    if let Ok(palette) = env::var("CHIP8_PALETTE") {
+        overrides.palette = Some(parse_palette(&palette)?);
+    }
+    if let Ok(font) = env::var("CHIP8_FONT") {
+        overrides.font = Some(font);
+    }
+    if let Ok(scaling) = env::var("CHIP8_SCALING") {
+        overrides.scaling = Some(scaling.parse()?);
+    }
+    if let Ok(filters) = env::var("CHIP8_FILTERS") {
+        overrides.filters = Some(filters.parse()?);
+    }
+    let overrides = overrides.merge(&options.settings());
+
+    // No trace, a trace to stdout, or a trace to a file
+    let trace = match &options.trace {
+        Some(path) if path.as_os_str() != "-" => {
+            Some(Some(File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?))
+        },
+        Some(_) => Some(None),
+        None => None
+    };
+
+    let (mut machine, mut info) = start_emu(&source, &db, &config, &overrides, &options)
+        .map_err(|e| format!("{}: {}", source, e))?;
+
+    if options.save || options.save_rom {
+        let path = config_path.as_ref()
+            .ok_or("nowhere to save settings: give --config, or set HOME or XDG_CONFIG_HOME")?;
+        let given = options.settings();
+        if options.save {
+            config.global = config.global.merge(&given);
+        }
+        if options.save_rom {
+            let rom = config.roms.entry(sha1_hex(machine.rom())).or_default();
+            *rom = rom.merge(&given);
+        }
+        config.save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("Saved settings to {}", path.display());
+    }
 
-        state.clock_tick(1);
+    // The window is set up for the first ROM and kept for any loaded later
+    let first = config.settings_for(&sha1_hex(machine.rom()), source.file_name()).merge(&overrides);
+    let mut video_options = VideoOptions::default();
+    video_options.scale = first.scale.unwrap_or(video_options.scale);
+    video_options.scaling = first.scaling.unwrap_or(video_options.scaling);
+    video_options.fullscreen = first.fullscreen.unwrap_or(false);
+    video_options.vsync = first.vsync.unwrap_or(video_options.vsync);
 
-        draw_screen(&mut window, &mut events, &state.video_buffer)?;
+    let sdl_context = sdl2::init()?;
+    let mut video = SdlVideo::new(&sdl_context, "CHIP-8", &video_options)?;
+    let mut keypad = SdlKeypad::new(&sdl_context, Keymap::qwerty(), Box::new(|_: &Keymap| ()))?;
+    let mut audio = SdlAudio::new(&sdl_context);
+
+    loop {
+        // The ROM's own settings beat the database, which beats the global settings
+        let sha1 = sha1_hex(machine.rom());
+        let rom = config.rom_settings(&sha1, source.file_name()).merge(&overrides);
+        let settings = config.global.merge(&rom);
+
+        let tickrate = rom.ipf
+            .or_else(|| info.as_ref().and_then(|i| i.tickrate))
+            .or(config.global.ipf)
+            .or_else(|| machine.platform.map(|p| p.tickrate()))
+            .unwrap_or(DEFAULT_TICKRATE);
+
+        let colors = info.as_ref().map(|i| i.colors.clone()).unwrap_or_default();
+        let (background, foreground) = match (rom.palette, colors.as_slice()) {
+            (Some(palette), _) => palette,
+            (None, [background, foreground, ..]) => (*background, *foreground),
+            (None, _) => config.global.palette.unwrap_or((video_options.background, video_options.foreground))
+        };
+        let title = match &info {
+            Some(info) => info.title.as_str(),
+            None => source.file_name().unwrap_or("Unknown")
+        };
+        video.set_rom(title, foreground, background);
+
+        let (keymap, on_remap) = rom_keymap(config_path.clone(), &sha1, &source, info.as_ref())?;
+        keypad.set_keymap(keymap, on_remap);
+        audio.muted = settings.mute.unwrap_or(false);
+
+        let mut emulator = Emulator::new(machine, tickrate).filters(settings.filters.unwrap_or_default());
+        match &trace {
+            Some(Some(file)) => {
+                let file = file.try_clone().map_err(|e| e.to_string())?;
+                emulator = emulator.trace(Box::new(BufWriter::new(file)));
+            },
+            Some(None) => emulator = emulator.trace(Box::new(io::stdout())),
+            None => ()
+        }
 
-        for event in events.poll_iter() {
-            match event {
-                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    println!("Quitting...");
-                    break 'main;
+        // A ROM that fails to load leaves the current one running
+        let mut exit = emulator.run(&mut video, &mut keypad, &mut audio, &mut FrameTimer::new())?;
+        loop {
+            let name = match exit {
+                Exit::Quit => return Ok(()),
+                Exit::Load(name) => name
+            };
+            let next = RomSource::parse(&name);
+            match start_emu(&next, &db, &config, &overrides, &options) {
+                Ok(loaded) => {
+                    source = next;
+                    (machine, info) = loaded;
+                    break;
+                },
+                Err(e) => {
+                    eprintln!("{}: {}", next, e);
+                    exit = emulator.run(&mut video, &mut keypad, &mut audio, &mut FrameTimer::new())?;
                 }
-                _ => ()
             }
         }
-
-        println!("ProcState: {}, Instruction: {}, KeyPress: {:?}", &state, &opcode, io.clone().get());
-        std::thread::sleep(Duration::from_millis(delay));
     }
-
-    Ok(())
 }
 
-fn draw_screen(window: &mut Window, events: &mut EventPump, video_buffer: &[u64]) -> Result<(), String> {
-    let mut surface = window.surface(events)?;
-    for row in 0 .. SCREEN_HEIGHT {
-        for column in 0 .. SCREEN_WIDTH {
-            let xpos = (column as i32) * (SCALING_FACTOR as i32);
-            let ypos = (row as i32) * (SCALING_FACTOR as i32);
-
-            let pixel = Rect::new(xpos, ypos, SCALING_FACTOR, SCALING_FACTOR);
-            let pixel_on = (video_buffer[row] >> ((SCREEN_WIDTH as u64) - 1 - (column as u64))) & 0x1 == 0x1;
-            let color = if pixel_on {
-                Color::RGB(255, 255, 255)
-            } else {
-                Color::RGB(0, 0,0)
-            };
-
-            surface.fill_rect(pixel, color)?
-        }
+/// Keyboard layout from the config file, then controller defaults and database actions, then
+/// the config file's bindings for this ROM. Keys remapped in the emulator are saved in the ROM's
+/// section of the config file.
+fn rom_keymap(path: Option<PathBuf>, sha1: &str, source: &RomSource, info: Option<&RomInfo>) -> Result<(Keymap, OnRemap), String> {
+    // Read afresh, so remapping saves over the latest file rather than the one read at startup
+    let mut config = match &path {
+        Some(path) => Config::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Config::default()
+    };
+    let mut extra = pad_keymap();
+    if let Some(info) = info {
+        extra.extend(&action_keymap(info));
     }
+    let keymap = config.keymap_for(&Keymap::qwerty(), &extra, sha1, source.file_name());
+    let sha1 = sha1.to_string();
+
+    let on_remap: OnRemap = Box::new(move |bindings: &Keymap| {
+        let rom = config.roms.entry(sha1.clone()).or_default();
+        *rom = rom.merge(&Settings { keys: Some(bindings.clone()), ..Settings::default() });
+        if let Some(path) = &path {
+            if let Err(e) = config.save(path) {
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+    });
 
-    surface.finish()?;
-    Ok(())
+    Ok((keymap, on_remap))
 }
 
-fn poll_keys(events: &mut EventPump, io: Rc<Cell<Option<u8>>>) {
-    let key = events.keyboard_state().pressed_scancodes().filter_map(Keycode::from_scancode).last();
-
-    match key {
-        None => io.set(Option::None),
-        Some(Keycode::Num1) => io.set(Option::Some(0x1)),
-        Some(Keycode::Num2) => io.set(Option::Some(0x2)),
-        Some(Keycode::Num3) => io.set(Option::Some(0x3)),
-        Some(Keycode::Num4) => io.set(Option::Some(0xC)),
-        Some(Keycode::Q) => io.set(Option::Some(0x4)),
-        Some(Keycode::W) => io.set(Option::Some(0x5)),
-        Some(Keycode::E) => io.set(Option::Some(0x6)),
-        Some(Keycode::R) => io.set(Option::Some(0xD)),
-        Some(Keycode::A) => io.set(Option::Some(0x7)),
-        Some(Keycode::S) => io.set(Option::Some(0x8)),
-        Some(Keycode::D) => io.set(Option::Some(0x9)),
-        Some(Keycode::F) => io.set(Option::Some(0xE)),
-        Some(Keycode::Z) => io.set(Option::Some(0xA)),
-        Some(Keycode::X) => io.set(Option::Some(0x0)),
-        Some(Keycode::C) => io.set(Option::Some(0xB)),
-        Some(Keycode::V) => io.set(Option::Some(0xF)),
-        Some(_) => io.set(Option::None)
+fn load_font(name: &str) -> Result<Font, String> {
+    if let Ok(small) = name.parse::<SmallFont>() {
+        return Ok(Font::from(small));
     }
+    let mut file = File::open(name).map_err(|e| e.to_string())?;
+    Font::load(&mut file).map_err(|e| e.to_string())
 }
 
-fn start_emu(filename: &String, io: Rc<Cell<Option<u8>>>) -> ProcState {
-    let mut f = File::open(&Path::new(filename)).expect(&format!("File not found: {}", filename));
-
-    let cart = Cartridge::load(&mut f);
+fn start_emu(source: &RomSource, db: &RomDatabase, config: &Config, overrides: &Settings, options: &Options) -> Result<(Machine, Option<RomInfo>), String> {
+    let cart = source.load().map_err(|e| e.to_string())?;
     println!("Cart Loaded. Size={} bytes", cart.size);
 
-    let mut mem = [0x0; MAX_MEMORY_SIZE];
+    let settings = config.settings_for(&cart.sha1(), source.file_name()).merge(overrides);
+    let font = match &settings.font {
+        Some(name) => load_font(name).map_err(|e| format!("{}: {}", name, e))?,
+        None => Font::default()
+    };
 
-    // Load cartridge into memory
-    for i in 0 .. cart.size {
-        mem[i + 0x200] = cart.buffer[i];
+    let info = db.lookup(&cart).cloned();
+    if let Some(info) = &info {
+        println!("Recognised {} ({})", info.title, info.platform.map(|p| p.id()).unwrap_or("unknown platform"));
     }
 
-    // Load the font sprites into memory
-    for i in 0 .. FONT_SPRITES.len() {
-        mem[i] = FONT_SPRITES[i];
+    // A platform from the command line replaces the database's, quirks and all
+    let platform = options.platform
+        .or_else(|| info.as_ref().and_then(|i| i.platform))
+        .or_else(|| source.platform());
+    let mut quirks = match (&info, options.platform) {
+        (Some(info), None) => info.quirks,
+        _ => platform.map(|p| p.quirks()).unwrap_or_default()
+    };
+    options.apply_quirks(&mut quirks);
+
+    let mut builder = Machine::builder(&cart)
+        .platform(platform)
+        .quirks(quirks)
+        .font(font)
+        .seed(options.seed)
+        .vip_timing(options.vip_timing);
+    if let Some(font_address) = settings.font_address {
+        builder = builder.font_address(font_address);
     }
+    let machine = builder.build().map_err(|e| e.to_string())?;
 
-    ProcState::new(mem, io)
-}
\ No newline at end of file
+    Ok((machine, info))
+}
@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::EventPump;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{GameControllerSubsystem, Sdl};
+
+use chip8_core::color::Rgb;
+use chip8_core::db::RomInfo;
+use chip8_core::display::Frame;
+use chip8_core::frontend::{Audio, Command, Keypad, Scaling, Video};
+use chip8_core::keymap::{Keymap, Remapper};
+use chip8_core::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+const DEFAULT_SCALE: u32 = 12;
+const TONE_FREQUENCY: f32 = 440.0;
+const TONE_VOLUME: f32 = 0.1;
+
+pub struct VideoOptions {
+    /// Initial window size as a multiple of the screen size.
+    pub scale: u32,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub foreground: Rgb,
+    pub background: Rgb
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            scale: DEFAULT_SCALE,
+            scaling: Scaling::Integer,
+            fullscreen: false,
+            vsync: true,
+            foreground: Rgb::WHITE,
+            background: Rgb::BLACK
+        }
+    }
+}
+
+/// Draws the screen into a streaming texture once per frame and lets the GPU scale it.
+pub struct SdlVideo {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    title: String,
+    scaling: Scaling,
+    foreground: Rgb,
+    background: Rgb
+}
+
+impl SdlVideo {
+    pub fn new(sdl_context: &Sdl, title: &str, options: &VideoOptions) -> Result<SdlVideo, String> {
+        let video_subsystem = sdl_context.video()?;
+
+        let width = (SCREEN_WIDTH as u32) * options.scale;
+        let height = (SCREEN_HEIGHT as u32) * options.scale;
+        let mut window = video_subsystem.window(title, width, height);
+        window.position_centered().resizable();
+        if options.fullscreen {
+            window.fullscreen_desktop();
+        }
+        let window = window.build().map_err(|e| e.to_string())?;
+
+        let mut canvas = window.into_canvas();
+        if options.vsync {
+            canvas = canvas.present_vsync();
+        }
+        let canvas = canvas.accelerated().build().map_err(|e| e.to_string())?;
+        let texture = canvas.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|e| e.to_string())?;
+
+        Ok(SdlVideo {
+            canvas,
+            texture,
+            title: title.to_string(),
+            scaling: options.scaling,
+            foreground: options.foreground,
+            background: options.background
+        })
+    }
+
+    /// Changes the window title and colours for a newly loaded ROM.
+    pub fn set_rom(&mut self, title: &str, foreground: Rgb, background: Rgb) {
+        self.title = title.to_string();
+        self.foreground = foreground;
+        self.background = background;
+    }
+}
+
+impl Video for SdlVideo {
+    fn present(&mut self, frame: &Frame) -> Result<(), String> {
+        let (foreground, background) = (self.foreground, self.background);
+        self.texture.with_lock(None, |pixels: &mut [u8], pitch: usize| {
+            for row in 0 .. SCREEN_HEIGHT {
+                for column in 0 .. SCREEN_WIDTH {
+                    let color = background.mix(foreground, frame.pixel(column, row));
+
+                    let offset = row * pitch + column * 3;
+                    pixels[offset .. offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+                }
+            }
+        })?;
+
+        let window_size = self.canvas.output_size()?;
+        let (x, y, width, height) = self.scaling.viewport(window_size, (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32));
+
+        self.canvas.set_draw_color(Color::RGB(background.r, background.g, background.b));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, Rect::new(x, y, width, height))?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+        window.set_fullscreen(fullscreen)
+    }
+
+    fn set_status(&mut self, status: &str) -> Result<(), String> {
+        let title = format!("{} - {}", self.title, status);
+        self.canvas.window_mut().set_title(&title).map_err(|e| e.to_string())
+    }
+}
+
+/// Called with the new bindings when the user finishes remapping.
+pub type OnRemap = Box<dyn FnMut(&Keymap)>;
+
+pub struct SdlKeypad {
+    events: EventPump,
+    controllers: Option<GameControllerSubsystem>,
+    /// Kept open so their events keep coming.
+    open_controllers: Vec<GameController>,
+    keymap: Keymap,
+    pad_held: Vec<String>,
+    held: Option<u8>,
+    pending: VecDeque<Command>,
+    remapper: Option<Remapper>,
+    on_remap: OnRemap
+}
+
+impl SdlKeypad {
+    pub fn new(sdl_context: &Sdl, keymap: Keymap, on_remap: OnRemap) -> Result<SdlKeypad, String> {
+        let controllers = sdl_context.game_controller()
+            .map_err(|e| eprintln!("Game controllers disabled: {}", e))
+            .ok();
+
+        let mut keypad = SdlKeypad {
+            events: sdl_context.event_pump()?,
+            controllers,
+            open_controllers: Vec::new(),
+            keymap,
+            pad_held: Vec::new(),
+            held: None,
+            pending: VecDeque::new(),
+            remapper: None,
+            on_remap
+        };
+
+        let available = keypad.controllers.as_ref().map(|c| c.num_joysticks()).transpose()?.unwrap_or(0);
+        for index in 0 .. available {
+            keypad.open_controller(index);
+        }
+        Ok(keypad)
+    }
+
+    /// Switches to the keymap for a newly loaded ROM.
+    pub fn set_keymap(&mut self, keymap: Keymap, on_remap: OnRemap) {
+        self.keymap = keymap;
+        self.on_remap = on_remap;
+    }
+
+    fn open_controller(&mut self, index: u32) {
+        if let Some(controllers) = &self.controllers {
+            if controllers.is_game_controller(index) {
+                match controllers.open(index) {
+                    Ok(controller) => self.open_controllers.push(controller),
+                    Err(e) => eprintln!("Cannot open game controller {}: {}", index, e)
+                }
+            }
+        }
+    }
+
+    /// Feeds an event to the remapping screen, returning false if it was not for the remapper.
+    fn remap(&mut self, event: &Event) -> bool {
+        let remapper = match &mut self.remapper {
+            Some(remapper) => remapper,
+            None => return false
+        };
+
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                self.remapper = None;
+                return true;
+            },
+            Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => remapper.skip(),
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => remapper.bind(&keycode.name()),
+            Event::ControllerButtonDown { button, .. } => remapper.bind(&pad_input(*button)),
+            _ => return false
+        }
+
+        if remapper.key().is_none() {
+            let bindings = remapper.keymap().clone();
+            self.keymap.extend(&bindings);
+            (self.on_remap)(&bindings);
+            self.remapper = None;
+        }
+        true
+    }
+}
+
+impl Keypad for SdlKeypad {
+    fn poll(&mut self) -> Option<Command> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        for event in events {
+            if self.remap(&event) {
+                continue;
+            }
+
+            match event {
+                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    println!("Quitting...");
+                    self.pending.push_back(Command::Quit);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    self.remapper = Some(Remapper::new());
+                }
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    self.pending.extend(hotkey(keycode));
+                }
+                Event::DropFile { filename, .. } => self.pending.push_back(Command::Load(filename)),
+                Event::ControllerDeviceAdded { which, .. } => self.open_controller(which),
+                Event::ControllerButtonDown { button, .. } => self.pad_held.push(pad_input(button)),
+                Event::ControllerButtonUp { button, .. } => {
+                    let input = pad_input(button);
+                    self.pad_held.retain(|held| *held != input);
+                }
+                _ => ()
+            }
+        }
+
+        let keys: Vec<String> = self.events.keyboard_state()
+            .pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .map(|keycode| keycode.name())
+            .collect();
+        self.held = keys.iter()
+            .chain(self.pad_held.iter())
+            .rev()
+            .find_map(|input| self.keymap.get(input));
+        self.pending.pop_front()
+    }
+
+    fn held(&self) -> Option<u8> {
+        self.held
+    }
+
+    fn overlay(&self) -> Option<Frame> {
+        self.remapper.as_ref().map(|remapper| remapper.frame())
+    }
+}
+
+/// Keys that control the emulator rather than the game. F1 opens the remapping screen.
+fn hotkey(keycode: Keycode) -> Option<Command> {
+    match keycode {
+        Keycode::F2 => Some(Command::Reset),
+        Keycode::F3 | Keycode::Pause => Some(Command::TogglePause),
+        Keycode::F4 => Some(Command::FrameAdvance),
+        Keycode::F5 => Some(Command::SlowDown),
+        Keycode::F6 => Some(Command::SpeedUp),
+        Keycode::F7 => Some(Command::NormalSpeed),
+        Keycode::F11 => Some(Command::ToggleFullscreen),
+        _ => None
+    }
+}
+
+fn pad_input(button: Button) -> String {
+    format!("pad:{}", button.string())
+}
+
+/// Game controller buttons for the common CHIP-8 controls: 2/4/6/8 as arrows and 5 as fire.
+pub fn pad_keymap() -> Keymap {
+    let mut keymap = Keymap::default();
+    let buttons = [
+        (Button::DPadUp, 0x2),
+        (Button::DPadDown, 0x8),
+        (Button::DPadLeft, 0x4),
+        (Button::DPadRight, 0x6),
+        (Button::A, 0x5),
+        (Button::B, 0x0),
+        (Button::X, 0xA),
+        (Button::Y, 0xB),
+        (Button::Back, 0xE),
+        (Button::Start, 0xF)
+    ];
+    for (button, key) in buttons.iter() {
+        keymap.bind(&pad_input(*button), *key);
+    }
+    keymap
+}
+
+/// Keyboard keys and controller buttons for the game actions named by the ROM database.
+pub fn action_keymap(info: &RomInfo) -> Keymap {
+    let mut keymap = Keymap::default();
+    for (action, key) in &info.keys {
+        let (keyboard, button) = match action.as_str() {
+            "up" => (Keycode::Up, Some(Button::DPadUp)),
+            "down" => (Keycode::Down, Some(Button::DPadDown)),
+            "left" => (Keycode::Left, Some(Button::DPadLeft)),
+            "right" => (Keycode::Right, Some(Button::DPadRight)),
+            "a" => (Keycode::Space, Some(Button::A)),
+            "b" => (Keycode::Return, Some(Button::B)),
+            "player2Up" => (Keycode::Kp8, None),
+            "player2Down" => (Keycode::Kp2, None),
+            "player2Left" => (Keycode::Kp4, None),
+            "player2Right" => (Keycode::Kp6, None),
+            "player2A" => (Keycode::Kp0, None),
+            "player2B" => (Keycode::KpEnter, None),
+            _ => continue
+        };
+
+        keymap.bind(&keyboard.name(), *key);
+        if let Some(button) = button {
+            keymap.bind(&pad_input(button), *key);
+        }
+    }
+    keymap
+}
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 { TONE_VOLUME } else { -TONE_VOLUME };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+/// Plays the buzzer as a square wave, or nothing if the host has no audio device.
+pub struct SdlAudio {
+    device: Option<AudioDevice<SquareWave>>,
+    pub muted: bool
+}
+
+impl SdlAudio {
+    pub fn new(sdl_context: &Sdl) -> SdlAudio {
+        let device = open_tone(sdl_context)
+            .map_err(|e| eprintln!("Audio disabled: {}", e))
+            .ok();
+
+        SdlAudio { device, muted: false }
+    }
+}
+
+fn open_tone(sdl_context: &Sdl) -> Result<AudioDevice<SquareWave>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let desired = AudioSpecDesired { freq: Some(44_100), channels: Some(1), samples: None };
+
+    audio_subsystem.open_playback(None, &desired, |spec| {
+        SquareWave { phase_inc: TONE_FREQUENCY / spec.freq as f32, phase: 0.0 }
+    })
+}
+
+impl Audio for SdlAudio {
+    fn set_tone(&mut self, on: bool) {
+        match &self.device {
+            Some(device) if on && !self.muted => device.resume(),
+            Some(device) => device.pause(),
+            None => ()
+        }
+    }
+}